@@ -0,0 +1,665 @@
+use std::error::Error;
+use std::fmt;
+
+use models::*;
+use parser::is_username_char;
+
+/// Maximum length of a Gitter message text
+pub const MAX_MESSAGE_LENGTH: usize = 4096;
+
+/// Characters which have a meaning in Gitter flavored Markdown
+const MARKDOWN_CHARS: &str = "\\`*_[]<>#~|@";
+
+/// Returns the length of the text the way Gitter measures it (UTF-16 code units)
+pub fn message_length(text: &str) -> usize {
+    text.encode_utf16().count()
+}
+
+/// Escapes Markdown in `text`, so it is shown to users as is
+pub fn escape_markdown(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for (i, line) in text.split('\n').enumerate() {
+        if i > 0 {
+            escaped.push('\n');
+        }
+
+        let content = line.trim_start();
+        escaped.push_str(&line[..line.len() - content.len()]);
+
+        // List markers, ordered list numbers, rules and heading underlines
+        // only count at the line start
+        let digits = content.chars().take_while(|c| c.is_ascii_digit()).count();
+        let number_end = &content[digits..];
+        let bullet = content.starts_with("- ") || content.starts_with("+ ") || content == "-" || content == "+";
+        let number = digits > 0
+            && (number_end.starts_with(". ") || number_end.starts_with(") ") || number_end == "." || number_end == ")");
+
+        if bullet || is_rule(content) {
+            escaped.push('\\');
+        } else if number {
+            escaped.push_str(&content[..digits]);
+            escaped.push('\\');
+            escaped.push_str(&escape_inline(number_end));
+            continue;
+        }
+
+        escaped.push_str(&escape_inline(content));
+    }
+
+    escaped
+}
+
+/// Determines whether the line is a horizontal rule or a setext heading underline
+/// made of `-` or `=`, the other rule characters are escaped anyway
+fn is_rule(line: &str) -> bool {
+    match line.chars().next() {
+        Some(c) if c == '-' || c == '=' => line.chars().all(|other| other == c || other == ' ' || other == '\t'),
+        _ => false,
+    }
+}
+
+fn escape_inline(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        if MARKDOWN_CHARS.contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+
+    escaped
+}
+
+/// Returns a backtick fence longer than any backtick run in `text`
+fn code_fence(text: &str, min_len: usize) -> String {
+    let mut longest = 0;
+    let mut current = 0;
+
+    for c in text.chars() {
+        if c == '`' {
+            current += 1;
+            longest = longest.max(current);
+        } else {
+            current = 0;
+        }
+    }
+
+    "`".repeat(min_len.max(longest + 1))
+}
+
+/// Error returned when the built message exceeds `MAX_MESSAGE_LENGTH`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MessageTooLong {
+    /// Length of the built message
+    pub length: usize,
+
+    /// Maximum allowed length
+    pub max_length: usize,
+}
+
+impl fmt::Display for MessageTooLong {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "message is {} characters long, the limit is {}", self.length, self.max_length)
+    }
+}
+
+impl Error for MessageTooLong {
+    fn description(&self) -> &str {
+        "message is too long"
+    }
+}
+
+/// Builds Markdown text of an outgoing message.
+///
+/// User provided text is escaped, so it cannot break the formatting:
+///
+/// ```rust,ignore
+/// let text = MessageBuilder::new()
+///     .mention(&user)
+///     .text(" build failed, see ")
+///     .issue(42)
+///     .code_block("rust", "fn main() {}")
+///     .build()?;
+///
+/// api.send_message(&room_id, text)?;
+/// ```
+#[derive(Debug, Clone)]
+pub struct MessageBuilder {
+    text: String,
+    max_length: usize,
+}
+
+impl Default for MessageBuilder {
+    fn default() -> MessageBuilder {
+        MessageBuilder::new()
+    }
+}
+
+impl MessageBuilder {
+    /// Creates empty message builder
+    pub fn new() -> MessageBuilder {
+        MessageBuilder {
+            text: String::new(),
+            max_length: MAX_MESSAGE_LENGTH,
+        }
+    }
+
+    /// Overrides the maximum message length checked by `build`
+    pub fn max_length(mut self, max_length: usize) -> MessageBuilder {
+        self.max_length = max_length;
+        self
+    }
+
+    /// Appends plain text, Markdown in it is escaped
+    pub fn text<S>(mut self, text: S) -> MessageBuilder
+        where S: AsRef<str>
+    {
+        self.text.push_str(&escape_markdown(text.as_ref()));
+        self
+    }
+
+    /// Appends Markdown as is
+    pub fn markdown<S>(mut self, markdown: S) -> MessageBuilder
+        where S: AsRef<str>
+    {
+        self.text.push_str(markdown.as_ref());
+        self
+    }
+
+    /// Appends bold text
+    pub fn bold<S>(mut self, text: S) -> MessageBuilder
+        where S: AsRef<str>
+    {
+        self.text.push_str(&format!("**{}**", escape_inline(text.as_ref())));
+        self
+    }
+
+    /// Appends italic text
+    pub fn italic<S>(mut self, text: S) -> MessageBuilder
+        where S: AsRef<str>
+    {
+        self.text.push_str(&format!("*{}*", escape_inline(text.as_ref())));
+        self
+    }
+
+    /// Appends inline code
+    pub fn code<S>(mut self, code: S) -> MessageBuilder
+        where S: AsRef<str>
+    {
+        let code = code.as_ref();
+        let fence = code_fence(code, 1);
+
+        // Spaces keep backticks at the edges from merging with the fence
+        if code.starts_with('`') || code.ends_with('`') {
+            self.text.push_str(&format!("{} {} {}", fence, code, fence));
+        } else {
+            self.text.push_str(&format!("{}{}{}", fence, code, fence));
+        }
+
+        self
+    }
+
+    /// Appends a mention of the user. Characters which cannot be part of
+    /// a username end the mention and are escaped
+    pub fn mention(mut self, user: &User) -> MessageBuilder {
+        let end = user.username.find(|c| !is_username_char(c)).unwrap_or(user.username.len());
+        let (username, rest) = user.username.split_at(end);
+
+        self.text.push('@');
+        self.text.push_str(username);
+        self.text.push_str(&escape_inline(rest));
+        self
+    }
+
+    /// Appends a mention of everybody in the room
+    pub fn mention_all(mut self) -> MessageBuilder {
+        self.text.push_str("@/all");
+        self
+    }
+
+    /// Appends a reference to the issue of the room's repository
+    pub fn issue(mut self, number: u32) -> MessageBuilder {
+        self.text.push_str(&format!("#{}", number));
+        self
+    }
+
+    /// Appends a reference to the issue of another repository
+    pub fn repo_issue<S>(mut self, repo: S, number: u32) -> MessageBuilder
+        where S: AsRef<str>
+    {
+        self.text.push_str(&format!("{}#{}", repo.as_ref(), number));
+        self
+    }
+
+    /// Appends a link
+    pub fn link<T, U>(mut self, title: T, url: U) -> MessageBuilder
+        where T: AsRef<str>,
+              U: AsRef<str>
+    {
+        let url = url.as_ref()
+            .replace(' ', "%20")
+            .replace('(', "%28")
+            .replace(')', "%29");
+
+        self.text.push_str(&format!("[{}]({})", escape_inline(title.as_ref()), url));
+        self
+    }
+
+    /// Starts a new line
+    pub fn newline(mut self) -> MessageBuilder {
+        self.text.push('\n');
+        self
+    }
+
+    /// Appends fenced code block, `language` may be empty. Only its first word
+    /// is used: a line break would end the fence line and a backtick would
+    /// turn the fence into inline code
+    pub fn code_block<L, S>(mut self, language: L, code: S) -> MessageBuilder
+        where L: AsRef<str>,
+              S: AsRef<str>
+    {
+        let language = language.as_ref().split(|c: char| c.is_whitespace() || c == '`').next().unwrap_or("");
+        let code = code.as_ref().trim_end_matches('\n');
+        let fence = code_fence(code, 3);
+
+        self.start_block();
+        self.text.push_str(&format!("{}{}\n{}\n{}\n", fence, language, code, fence));
+        self
+    }
+
+    /// Appends a quote
+    pub fn quote<S>(mut self, text: S) -> MessageBuilder
+        where S: AsRef<str>
+    {
+        self.start_block();
+
+        for line in text.as_ref().lines() {
+            self.text.push_str("> ");
+            self.text.push_str(&escape_markdown(line));
+            self.text.push('\n');
+        }
+
+        self.text.push('\n');
+        self
+    }
+
+    /// Appends a bulleted list
+    pub fn list<I, S>(mut self, items: I) -> MessageBuilder
+        where I: IntoIterator<Item = S>,
+              S: AsRef<str>
+    {
+        self.start_block();
+
+        for item in items {
+            self.text.push_str("- ");
+            self.text.push_str(&escape_markdown(item.as_ref()));
+            self.text.push('\n');
+        }
+
+        self.text.push('\n');
+        self
+    }
+
+    /// Appends a numbered list
+    pub fn numbered_list<I, S>(mut self, items: I) -> MessageBuilder
+        where I: IntoIterator<Item = S>,
+              S: AsRef<str>
+    {
+        self.start_block();
+
+        for (i, item) in items.into_iter().enumerate() {
+            self.text.push_str(&format!("{}. ", i + 1));
+            self.text.push_str(&escape_markdown(item.as_ref()));
+            self.text.push('\n');
+        }
+
+        self.text.push('\n');
+        self
+    }
+
+    /// Returns the length of the message built so far
+    pub fn len(&self) -> usize {
+        message_length(self.text.trim_end())
+    }
+
+    /// Determines whether nothing was added to the message yet
+    pub fn is_empty(&self) -> bool {
+        self.text.trim_end().is_empty()
+    }
+
+    /// Returns message text, or an error if it is longer than Gitter allows
+    pub fn build(self) -> Result<String, MessageTooLong> {
+        let length = self.len();
+
+        if length > self.max_length {
+            return Err(MessageTooLong {
+                length,
+                max_length: self.max_length,
+            });
+        }
+
+        let mut text = self.text;
+        let trimmed_len = text.trim_end().len();
+        text.truncate(trimmed_len);
+
+        Ok(text)
+    }
+
+    /// Block elements must start on their own line
+    fn start_block(&mut self) {
+        if !self.text.is_empty() && !self.text.ends_with('\n') {
+            self.text.push('\n');
+        }
+    }
+}
+
+/// Splits text longer than `max_length` into messages.
+///
+/// Text is split between paragraphs and code blocks where possible, then
+/// between lines, then between words. A code block which does not fit into
+/// one message is closed at the end of the message and reopened with the same
+/// fence and language in the next one, so every message renders on its own:
+///
+/// ```rust,ignore
+/// for chunk in split_message(&log, MAX_MESSAGE_LENGTH) {
+///     api.send_message(&room_id, chunk)?;
+/// }
+/// ```
+///
+/// Blank text gives no messages
+pub fn split_message(text: &str, max_length: usize) -> Vec<String> {
+    if text.trim().is_empty() {
+        return Vec::new();
+    }
+
+    if message_length(text) <= max_length {
+        return vec![text.to_string()];
+    }
+
+    let mut splitter = Splitter {
+        max_length,
+        chunks: Vec::new(),
+        current: String::new(),
+        has_content: false,
+        fence: None,
+        fence_start: 0,
+    };
+
+    for block in blocks(text) {
+        splitter.push_block(&block);
+    }
+
+    splitter.finish()
+}
+
+/// Fence of a code block: the backticks or tildes and the info string, e.g. the language
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Fence {
+    marker: String,
+    info: String,
+}
+
+impl Fence {
+    /// Parses the opening line of a fenced code block
+    pub fn open(line: &str) -> Option<Fence> {
+        let line = line.trim_end_matches(&['\r', '\n'][..]);
+        let trimmed = line.trim_start_matches(' ');
+        let c = trimmed.chars().next()?;
+
+        if line.len() - trimmed.len() > 3 || (c != '`' && c != '~') {
+            return None;
+        }
+
+        let marker_len = trimmed.chars().take_while(|&m| m == c).count();
+        let info = trimmed[marker_len..].trim_end();
+
+        if marker_len < 3 || (c == '`' && info.contains('`')) {
+            return None;
+        }
+
+        Some(Fence {
+            marker: trimmed[..marker_len].to_string(),
+            info: info.to_string(),
+        })
+    }
+
+    /// Determines whether the line closes the code block
+    pub fn is_closed_by(&self, line: &str) -> bool {
+        let line = line.trim_end_matches(&['\r', '\n'][..]);
+        let trimmed = line.trim_start_matches(' ').trim_end();
+        let c = self.marker.chars().next().unwrap_or('`');
+
+        line.len() - line.trim_start_matches(' ').len() <= 3
+            && trimmed.len() >= self.marker.len()
+            && trimmed.chars().all(|m| m == c)
+    }
+
+    fn reopen(&self) -> String {
+        format!("{}{}\n", self.marker, self.info)
+    }
+}
+
+/// Line of the text with the code block it is in
+struct Line<'a> {
+    text: &'a str,
+
+    /// Code block open before the line
+    fence_before: Option<Fence>,
+
+    /// Code block open after the line
+    fence_after: Option<Fence>,
+}
+
+/// Splits text into lines, keeping line breaks, and tracks code blocks
+fn lines(text: &str) -> Vec<Line<'_>> {
+    let mut lines = Vec::new();
+    let mut fence: Option<Fence> = None;
+    let mut rest = text;
+
+    while !rest.is_empty() {
+        let end = rest.find('\n').map_or(rest.len(), |i| i + 1);
+        let line = &rest[..end];
+        rest = &rest[end..];
+
+        let fence_after = match fence {
+            Some(ref open) if open.is_closed_by(line) => None,
+            Some(ref open) => Some(open.clone()),
+            None => Fence::open(line),
+        };
+
+        lines.push(Line {
+            text: line,
+            fence_before: fence,
+            fence_after: fence_after.clone(),
+        });
+        fence = fence_after;
+    }
+
+    lines
+}
+
+/// Groups lines into paragraphs and code blocks, the preferred places to split
+fn blocks(text: &str) -> Vec<Vec<Line<'_>>> {
+    let mut blocks: Vec<Vec<Line>> = Vec::new();
+
+    for line in lines(text) {
+        let starts_block = match blocks.last().and_then(|block| block.last()) {
+            None => true,
+            Some(previous) => {
+                let code_starts = line.fence_before.is_none() && line.fence_after.is_some();
+                let code_ended = previous.fence_before.is_some() && previous.fence_after.is_none();
+                let paragraph_ended =
+                    previous.fence_after.is_none() && previous.text.trim().is_empty() && !line.text.trim().is_empty();
+
+                code_starts || code_ended || paragraph_ended
+            }
+        };
+
+        if starts_block {
+            blocks.push(Vec::new());
+        }
+        blocks.last_mut().unwrap().push(line);
+    }
+
+    blocks
+}
+
+struct Splitter {
+    max_length: usize,
+    chunks: Vec<String>,
+    current: String,
+
+    /// Determines whether the current chunk has more than a reopened fence
+    has_content: bool,
+
+    /// Code block open at the end of the current chunk
+    fence: Option<Fence>,
+
+    /// Byte index of the opening fence of that code block in the current chunk
+    fence_start: usize,
+}
+
+impl Splitter {
+    fn push_block(&mut self, block: &[Line]) {
+        let text = block.iter().map(|line| line.text).collect::<String>();
+        let fence_after = block.last().and_then(|line| line.fence_after.clone());
+
+        if !self.fits(&text, &fence_after) && self.has_content {
+            self.flush();
+        }
+
+        if self.fits(&text, &fence_after) {
+            self.push(&text, fence_after);
+            return;
+        }
+
+        for line in block {
+            self.push_line(line);
+        }
+    }
+
+    fn push_line(&mut self, line: &Line) {
+        if !self.fits(line.text, &line.fence_after) && self.has_content {
+            self.flush();
+        }
+
+        if self.fits(line.text, &line.fence_after) {
+            self.push(line.text, line.fence_after.clone());
+            return;
+        }
+
+        // The line alone is too long, split it between words
+        let mut rest = line.text;
+        while !rest.is_empty() {
+            let available = self.max_length
+                .saturating_sub(message_length(&self.current) + close_length(&line.fence_before))
+                .max(1);
+            let end = split_point(rest, available);
+
+            if end == rest.len() {
+                self.push(rest, line.fence_after.clone());
+                break;
+            }
+
+            let (piece, tail) = rest.split_at(end);
+            self.push(piece, line.fence_before.clone());
+            self.flush();
+            rest = tail;
+        }
+    }
+
+    /// Determines whether the text fits into the current chunk,
+    /// along with the fence closing the chunk if needed
+    fn fits(&self, text: &str, fence_after: &Option<Fence>) -> bool {
+        message_length(&self.current) + message_length(text) + close_length(fence_after) <= self.max_length
+    }
+
+    fn push(&mut self, text: &str, fence_after: Option<Fence>) {
+        // Blank lines are not worth starting a message with
+        let text = if self.has_content || self.fence.is_some() {
+            text
+        } else {
+            text.trim_start_matches(&['\r', '\n'][..])
+        };
+
+        // Code blocks open at the start of a block, so at the start of the text
+        if self.fence.is_none() && fence_after.is_some() {
+            self.fence_start = self.current.len();
+        }
+
+        if !text.is_empty() {
+            self.current.push_str(text);
+            self.has_content = true;
+        }
+
+        self.fence = fence_after;
+    }
+
+    /// Ends the current chunk, closing its code block, and reopens the block in the next one
+    fn flush(&mut self) {
+        let chunk = match self.fence {
+            // A code block without code yet is left to the next chunk
+            Some(_) if !self.current[self.fence_start..].trim_end().contains('\n') => {
+                self.current[..self.fence_start].trim_end().to_string()
+            }
+            Some(ref fence) => format!("{}\n{}", self.current.trim_end(), fence.marker),
+            None => self.current.trim_end().to_string(),
+        };
+
+        if self.has_content && !chunk.trim().is_empty() {
+            self.chunks.push(chunk);
+        }
+
+        self.current = self.fence.as_ref().map(Fence::reopen).unwrap_or_default();
+        self.fence_start = 0;
+        self.has_content = false;
+    }
+
+    fn finish(mut self) -> Vec<String> {
+        if self.has_content {
+            // A code block left open by the text stays open
+            self.fence = None;
+            self.flush();
+        }
+
+        self.chunks
+    }
+}
+
+/// Length of the line closing an open code block
+fn close_length(fence: &Option<Fence>) -> usize {
+    fence.as_ref().map_or(0, |fence| 1 + fence.marker.len())
+}
+
+/// Returns the byte index to split `text` at, so the first part is at most
+/// `max_length` long: after the last whitespace, or at a character boundary
+fn split_point(text: &str, max_length: usize) -> usize {
+    if message_length(text) <= max_length {
+        return text.len();
+    }
+
+    let mut length = 0;
+    let mut end = 0;
+    let mut last_space = None;
+
+    for (i, c) in text.char_indices() {
+        length += c.len_utf16();
+        if length > max_length {
+            break;
+        }
+
+        end = i + c.len_utf8();
+        if c.is_whitespace() {
+            last_space = Some(end);
+        }
+    }
+
+    match last_space {
+        Some(space) => space,
+        None if end > 0 => end,
+        // Not even one character fits, split after it anyway to make progress
+        _ => text.chars().next().map_or(text.len(), char::len_utf8),
+    }
+}
@@ -0,0 +1,185 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use ids::*;
+
+/// Counters of the response cache
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Requests answered from the cache without asking Gitter
+    pub hits: u64,
+
+    /// Requests Gitter answered with `304 Not Modified`
+    pub revalidations: u64,
+
+    /// Requests which had to be fetched from Gitter
+    pub misses: u64,
+
+    /// Entries removed because a mutating request changed the resource
+    pub invalidations: u64,
+}
+
+/// Cached response body and its validators
+#[derive(Debug, Clone)]
+struct CacheEntry {
+    body: String,
+    etag: Option<String>,
+    last_modified: Option<String>,
+    fetched_at: Instant,
+}
+
+/// Conditional request headers of a stale entry
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct Validators {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+/// In-memory cache of GET responses, keyed by URL.
+///
+/// Fresh entries are returned without a request. Stale entries are
+/// revalidated with `If-None-Match` and `If-Modified-Since`.
+#[derive(Debug)]
+pub(crate) struct ResponseCache {
+    ttl: Duration,
+    entries: Mutex<HashMap<String, CacheEntry>>,
+    stats: Mutex<CacheStats>,
+
+    /// Number of invalidations, changed while `entries` is locked
+    generation: AtomicUsize,
+}
+
+impl ResponseCache {
+    pub fn new(ttl: Duration) -> ResponseCache {
+        ResponseCache {
+            ttl,
+            entries: Mutex::new(HashMap::new()),
+            stats: Mutex::new(CacheStats::default()),
+            generation: AtomicUsize::new(0),
+        }
+    }
+
+    /// Returns the body of a fresh entry
+    pub fn fresh(&self, url: &str) -> Option<String> {
+        let body = self.entries
+            .lock()
+            .unwrap()
+            .get(url)
+            .filter(|entry| entry.fetched_at.elapsed() < self.ttl)
+            .map(|entry| entry.body.clone());
+
+        if body.is_some() {
+            self.stats.lock().unwrap().hits += 1;
+        }
+
+        body
+    }
+
+    /// Returns validators of a stale entry to make the request conditional
+    pub fn validators(&self, url: &str) -> Option<Validators> {
+        self.entries
+            .lock()
+            .unwrap()
+            .get(url)
+            .filter(|entry| entry.etag.is_some() || entry.last_modified.is_some())
+            .map(|entry| Validators {
+                etag: entry.etag.clone(),
+                last_modified: entry.last_modified.clone(),
+            })
+    }
+
+    /// Marks the entry fresh again after `304 Not Modified` and returns its body
+    pub fn revalidate(&self, url: &str) -> Option<String> {
+        let body = self.entries.lock().unwrap().get_mut(url).map(|entry| {
+            entry.fetched_at = Instant::now();
+            entry.body.clone()
+        });
+
+        if body.is_some() {
+            self.stats.lock().unwrap().revalidations += 1;
+        }
+
+        body
+    }
+
+    /// Counts a request the cache could not answer
+    pub fn miss(&self) {
+        self.stats.lock().unwrap().misses += 1;
+    }
+
+    /// Returns the generation to pass to `store` for a request about to be sent
+    pub fn generation(&self) -> usize {
+        self.generation.load(Ordering::SeqCst)
+    }
+
+    /// Stores a response fetched from Gitter. A response requested before
+    /// an invalidation may show the old state, so it is not stored
+    pub fn store(&self, url: &str, body: String, validators: Validators, generation: usize) {
+        let mut entries = self.entries.lock().unwrap();
+        if self.generation.load(Ordering::SeqCst) != generation {
+            return;
+        }
+
+        entries.insert(
+            url.to_string(),
+            CacheEntry {
+                body,
+                etag: validators.etag,
+                last_modified: validators.last_modified,
+                fetched_at: Instant::now(),
+            },
+        );
+    }
+
+    /// Removes entries which a mutating request to `url` may have changed
+    pub fn invalidate(&self, url: &str) {
+        let mut entries = self.entries.lock().unwrap();
+        let before = entries.len();
+
+        entries.retain(|cached, _| !is_affected(url, cached));
+        self.generation.fetch_add(1, Ordering::SeqCst);
+
+        self.stats.lock().unwrap().invalidations += (before - entries.len()) as u64;
+    }
+
+    /// Removes all entries
+    pub fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        *self.stats.lock().unwrap()
+    }
+}
+
+/// Returns path segments of `url` without the query
+fn path_segments(url: &str) -> Vec<&str> {
+    let url = url.split('?').next().unwrap_or("");
+    let path = url.find("://").map_or(url, |i| &url[i + 3..]);
+
+    path.split('/').skip(1).filter(|s| !s.is_empty()).collect()
+}
+
+/// A mutation affects every cached response which mentions one of the IDs
+/// it touches, and the room and group lists. Mutations without IDs,
+/// like joining a room by URI, may affect anything
+fn is_affected(mutated: &str, cached: &str) -> bool {
+    // All Gitter IDs are ObjectIds, so any ID type can check the format
+    let ids = path_segments(mutated)
+        .into_iter()
+        .filter(|s| RoomId::new(*s).is_ok())
+        .map(|s| s.to_lowercase())
+        .collect::<Vec<_>>();
+
+    if ids.is_empty() {
+        return true;
+    }
+
+    let cached = path_segments(cached);
+
+    cached.iter().any(|s| ids.contains(&s.to_lowercase()))
+        || cached.last() == Some(&"rooms")
+        || cached.last() == Some(&"groups")
+}
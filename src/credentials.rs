@@ -0,0 +1,268 @@
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{self, Ordering};
+
+/// Gitter access token which never shows up in logs.
+///
+/// `Debug` and `Display` print `[redacted]`, and the memory
+/// holding the token is overwritten with zeros on drop. Only this copy
+/// is overwritten: each request carries the token in its `Authorization`
+/// header, which is freed like any other string
+#[derive(PartialEq, Eq)]
+pub struct SecretToken(String);
+
+impl SecretToken {
+    pub fn new<S>(token: S) -> SecretToken
+        where S: Into<String>
+    {
+        SecretToken(token.into())
+    }
+
+    /// Returns the token itself, to put it into a request
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl fmt::Debug for SecretToken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("SecretToken([redacted])")
+    }
+}
+
+impl fmt::Display for SecretToken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("[redacted]")
+    }
+}
+
+impl Drop for SecretToken {
+    fn drop(&mut self) {
+        // Volatile writes are not optimized away like writes to memory
+        // which is about to be freed. The whole capacity is overwritten,
+        // it may hold what was trimmed or truncated
+        unsafe {
+            let bytes = self.0.as_mut_vec();
+            let start = bytes.as_mut_ptr();
+            for i in 0..bytes.capacity() {
+                ptr::write_volatile(start.add(i), 0);
+            }
+        }
+        atomic::compiler_fence(Ordering::SeqCst);
+    }
+}
+
+/// Error returned when a token cannot be obtained
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CredentialError {
+    message: String,
+}
+
+impl CredentialError {
+    pub fn new<S>(message: S) -> CredentialError
+        where S: Into<String>
+    {
+        CredentialError {
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for CredentialError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "cannot get Gitter token: {}", self.message)
+    }
+}
+
+impl Error for CredentialError {
+    fn description(&self) -> &str {
+        "cannot get Gitter token"
+    }
+}
+
+/// Source of the Gitter token.
+///
+/// `Gitter` asks the provider for the token before every request,
+/// so a provider can rotate the token without rebuilding the client:
+///
+/// ```rust,ignore
+/// let api = Gitter::from_credentials(FileToken::new("/run/secrets/gitter-token"))?;
+/// ```
+pub trait CredentialProvider: Send + Sync {
+    /// Lends the current token to `f`. Providers keep the token
+    /// in a `SecretToken` and do not hand out copies of it
+    fn with_token(&self, f: &mut dyn FnMut(&str)) -> Result<(), CredentialError>;
+}
+
+/// Fixed token
+impl CredentialProvider for SecretToken {
+    fn with_token(&self, f: &mut dyn FnMut(&str)) -> Result<(), CredentialError> {
+        f(self.expose());
+        Ok(())
+    }
+}
+
+impl<P: CredentialProvider + ?Sized> CredentialProvider for Arc<P> {
+    fn with_token(&self, f: &mut dyn FnMut(&str)) -> Result<(), CredentialError> {
+        (**self).with_token(f)
+    }
+}
+
+impl<P: CredentialProvider + ?Sized> CredentialProvider for Box<P> {
+    fn with_token(&self, f: &mut dyn FnMut(&str)) -> Result<(), CredentialError> {
+        (**self).with_token(f)
+    }
+}
+
+/// Token which can be replaced at runtime.
+///
+/// Keep an `Arc` of it to rotate the token of a running client:
+///
+/// ```rust,ignore
+/// let token = Arc::new(RotatingToken::new(old_token));
+/// let api = Gitter::from_credentials(token.clone())?;
+///
+/// token.rotate(new_token);
+/// ```
+#[derive(Debug)]
+pub struct RotatingToken {
+    token: Mutex<SecretToken>,
+}
+
+impl RotatingToken {
+    pub fn new<S>(token: S) -> RotatingToken
+        where S: Into<String>
+    {
+        RotatingToken {
+            token: Mutex::new(SecretToken::new(token)),
+        }
+    }
+
+    /// Replaces the token, next requests use the new one
+    pub fn rotate<S>(&self, token: S)
+        where S: Into<String>
+    {
+        *self.token.lock().unwrap() = SecretToken::new(token);
+    }
+}
+
+impl CredentialProvider for RotatingToken {
+    fn with_token(&self, f: &mut dyn FnMut(&str)) -> Result<(), CredentialError> {
+        self.token.lock().unwrap().with_token(f)
+    }
+}
+
+/// Token read from an environment variable on every request
+#[derive(Debug, Clone)]
+pub struct EnvToken {
+    var: String,
+}
+
+impl EnvToken {
+    pub fn new<S>(var: S) -> EnvToken
+        where S: Into<String>
+    {
+        EnvToken { var: var.into() }
+    }
+}
+
+impl CredentialProvider for EnvToken {
+    fn with_token(&self, f: &mut dyn FnMut(&str)) -> Result<(), CredentialError> {
+        match env::var(&self.var).map(SecretToken) {
+            Ok(ref token) if !token.expose().trim().is_empty() => {
+                f(token.expose().trim());
+                Ok(())
+            }
+            _ => Err(CredentialError::new(format!("environment variable {} is not set", self.var))),
+        }
+    }
+}
+
+/// Token read from a file on every request, surrounding whitespace is ignored.
+/// Replacing the file rotates the token
+#[derive(Debug, Clone)]
+pub struct FileToken {
+    path: PathBuf,
+}
+
+impl FileToken {
+    pub fn new<P>(path: P) -> FileToken
+        where P: Into<PathBuf>
+    {
+        FileToken { path: path.into() }
+    }
+}
+
+impl CredentialProvider for FileToken {
+    fn with_token(&self, f: &mut dyn FnMut(&str)) -> Result<(), CredentialError> {
+        let contents = read_secret(&self.path)
+            .map_err(|e| CredentialError::new(format!("cannot read {}: {}", self.path.display(), e)))?;
+
+        let token = contents.expose().trim();
+        if token.is_empty() {
+            return Err(CredentialError::new(format!("{} is empty", self.path.display())));
+        }
+
+        f(token);
+        Ok(())
+    }
+}
+
+/// Reads the file into a buffer of its size, so the buffer
+/// is not reallocated and leaves no copies of the token behind
+fn read_secret(path: &Path) -> io::Result<SecretToken> {
+    let mut file = File::open(path)?;
+    let len = file.metadata()?.len() as usize;
+
+    let mut secret = SecretToken(String::with_capacity(len + 1));
+    file.read_to_string(&mut secret.0)?;
+
+    Ok(secret)
+}
+
+/// Keyring-like secret store, such as the OS keychain or a secret manager
+pub trait SecretStore: Send + Sync {
+    /// Returns the secret of the account of the service, `None` if there is none
+    fn get_secret(&self, service: &str, account: &str) -> Result<Option<SecretToken>, CredentialError>;
+}
+
+/// Token read from a `SecretStore` on every request
+#[derive(Debug, Clone)]
+pub struct StoreToken<S> {
+    store: S,
+    service: String,
+    account: String,
+}
+
+impl<S: SecretStore> StoreToken<S> {
+    pub fn new<T, U>(store: S, service: T, account: U) -> StoreToken<S>
+        where T: Into<String>,
+              U: Into<String>
+    {
+        StoreToken {
+            store,
+            service: service.into(),
+            account: account.into(),
+        }
+    }
+}
+
+impl<S: SecretStore> CredentialProvider for StoreToken<S> {
+    fn with_token(&self, f: &mut dyn FnMut(&str)) -> Result<(), CredentialError> {
+        let secret = self.store.get_secret(&self.service, &self.account)?.ok_or_else(|| {
+            CredentialError::new(format!("no secret for {} in {}", self.account, self.service))
+        })?;
+
+        secret.with_token(f)
+    }
+}
@@ -1,54 +1,217 @@
 use std::borrow::Cow;
 use std::collections::HashMap;
-use reqwest::{Client, IntoUrl};
-use reqwest::header::{Accept, Authorization, Bearer, ContentType, Headers};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use reqwest::{Client, Method};
+use reqwest::header::Headers;
 use serde::{Deserialize, Serialize};
+use serde_json;
+use std::fmt;
 use serde_urlencoded;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
+use builder::*;
+use cache::*;
+use credentials::*;
+use ids::*;
+use instrument::*;
+use middleware::*;
 use models::*;
+use ratelimit::*;
+use retry::*;
+use tls::*;
+use uri::*;
 
-#[derive(Debug)]
-pub struct Gitter<'a> {
-    token: Cow<'a, str>,
-    api_base_url: Cow<'a, str>,
+/// Gitter API client.
+///
+/// The client owns all its data, so it can be stored in a global or in the
+/// state of a web framework. Clones are cheap and share the connection pool,
+/// the credentials, the response cache and the room ID cache, so one client
+/// can be cloned into every thread:
+///
+/// ```rust,ignore
+/// let api = Gitter::new(token)?;
+///
+/// let worker = api.clone();
+/// thread::spawn(move || worker.send_message(&room_id, "Build started"));
+/// ```
+///
+/// The `with_*` methods change only the clone they are called on
+#[derive(Clone)]
+pub struct Gitter {
+    credentials: Arc<dyn CredentialProvider>,
+    api_base_url: String,
     client: Client,
+    room_ids: Arc<Mutex<HashMap<RoomUri, (RoomId, Instant)>>>,
+    room_id_ttl: Duration,
+    cache: Option<Arc<ResponseCache>>,
+    retry_policy: RetryPolicy,
+    send_limiter: Option<Arc<SendLimiter>>,
+    middlewares: Vec<Arc<dyn Middleware>>,
+}
+
+impl fmt::Debug for Gitter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Gitter")
+            .field("api_base_url", &self.api_base_url)
+            .field("cache", &self.cache)
+            .field("retry_policy", &self.retry_policy)
+            .field("send_limiter", &self.send_limiter)
+            .field("middlewares", &self.middlewares.len())
+            .finish()
+    }
 }
 
 const API_BASE_URL: &str = "https://api.gitter.im/v1";
 
+/// Timeout of requests sent by the HTTP client `Gitter` creates
+pub(crate) const TIMEOUT: Duration = Duration::from_secs(40);
+
+/// How long resolved room IDs are cached by default
+const ROOM_ID_TTL: Duration = Duration::from_secs(5 * 60);
+
 #[derive(Debug)]
 pub enum ApiError {
+    /// Response has no body, although the endpoint returns JSON
     EmptyResponse,
     BadResponse(String),
     BadRequest(String),
     RoomNotFound,
     UserNotFound,
+    /// Room URI matches several rooms the user is in
+    AmbiguousRoom(Vec<RoomId>),
+    /// Requested resource does not exist
+    NotFound(String),
+    /// The current user is not allowed to perform the operation
+    PermissionDenied(String),
+    /// The credential provider could not supply a token
+    Credentials(CredentialError),
+    /// The send is over the limit, it may go after the duration
+    RateLimited(Duration),
     Unknown(String),
 }
 
 type ApiResult<T> = Result<T, ApiError>;
 
-impl<'a> Gitter<'a> {
+/// Body of responses which only confirm the operation, e.g. `{"success":true}`
+type Confirmation = Option<serde_json::Value>;
+
+impl Gitter {
     /// New initializes the Gitter API client
-    pub fn new<S>(token: S) -> ApiResult<Gitter<'a>>
+    pub fn new<S>(token: S) -> ApiResult<Gitter>
+    where
+        S: Into<String>,
+    {
+        Self::builder(token).build()
+    }
+
+    /// Initializes the Gitter API client which takes the token from `credentials`
+    /// before every request, so the token can be rotated while the client runs
+    pub fn from_credentials<P>(credentials: P) -> ApiResult<Gitter>
+    where
+        P: CredentialProvider + 'static,
+    {
+        GitterBuilder::from_credentials(credentials).build()
+    }
+
+    /// Initializes the Gitter API client which sends requests through `client`,
+    /// reusing its connection pool, proxy and TLS settings
+    pub fn from_client<S>(token: S, client: Client) -> Gitter
+    where
+        S: Into<String>,
+    {
+        Self::from_parts(Arc::new(SecretToken::new(token)), client)
+    }
+
+    /// Creates a builder to configure the HTTP client, e.g. its TLS settings
+    pub fn builder<S>(token: S) -> GitterBuilder
     where
-        S: Into<Cow<'a, str>>,
+        S: Into<String>,
     {
-        match Client::builder().timeout(Duration::from_secs(40)).build() {
-            Ok(client) => Ok(Gitter {
-                token: token.into(),
-                api_base_url: API_BASE_URL.into(),
-                client,
-            }),
-            Err(e) => Err(ApiError::Unknown(e.to_string())),
+        GitterBuilder::from_credentials(SecretToken::new(token))
+    }
+
+    fn from_parts(credentials: Arc<dyn CredentialProvider>, client: Client) -> Gitter {
+        Gitter {
+            credentials,
+            api_base_url: API_BASE_URL.into(),
+            client,
+            room_ids: Arc::new(Mutex::new(HashMap::new())),
+            room_id_ttl: ROOM_ID_TTL,
+            cache: None,
+            retry_policy: RetryPolicy::new(),
+            send_limiter: None,
+            middlewares: Vec::new(),
+        }
+    }
+
+    /// Changes the API base url, e.g. to go through a proxy
+    /// or to talk to a local stand-in server in tests
+    pub fn with_api_base_url<S>(mut self, api_base_url: S) -> Gitter
+    where
+        S: Into<String>,
+    {
+        self.api_base_url = api_base_url.into();
+        self
+    }
+
+    /// Changes how long room IDs resolved by `get_room_id` are cached.
+    /// Zero disables the cache
+    pub fn with_room_id_ttl(mut self, ttl: Duration) -> Gitter {
+        self.room_id_ttl = ttl;
+        self
+    }
+
+    /// Changes how failed GET, PUT and DELETE requests are retried.
+    /// Use `RetryPolicy::none()` to disable retries
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Gitter {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Limits how fast messages are sent, see `SendLimits`
+    pub fn with_send_limits(mut self, limits: SendLimits) -> Gitter {
+        self.send_limiter = Some(Arc::new(SendLimiter::new(limits)));
+        self
+    }
+
+    /// Appends a middleware to the chain every API call passes through.
+    /// Middlewares run in the order they were added
+    pub fn with_middleware<M>(mut self, middleware: M) -> Gitter
+    where
+        M: Middleware + 'static,
+    {
+        self.middlewares.push(Arc::new(middleware));
+        self
+    }
+
+    /// Enables in-memory caching of GET responses.
+    ///
+    /// Responses younger than `ttl` are returned without a request, older ones
+    /// are revalidated with `If-None-Match`/`If-Modified-Since` when Gitter sent
+    /// an `ETag` or `Last-Modified` header. Sending, updating and deleting
+    /// invalidates the cached responses of the affected rooms, users and messages
+    pub fn with_cache(mut self, ttl: Duration) -> Gitter {
+        self.cache = Some(Arc::new(ResponseCache::new(ttl)));
+        self
+    }
+
+    /// Returns cache hit and miss counters, `None` if the cache is disabled
+    pub fn cache_stats(&self) -> Option<CacheStats> {
+        self.cache.as_ref().map(|cache| cache.stats())
+    }
+
+    /// Removes all cached responses
+    pub fn clear_cache(&self) {
+        if let Some(ref cache) = self.cache {
+            cache.clear();
         }
     }
 
     /// Returns the current user
     pub fn get_user(&self) -> ApiResult<User> {
         let full_url = format!("{}/user", self.api_base_url);
-        match self.get::<&str, Vec<User>>(&full_url) {
+        match self.get::<Vec<User>>(&full_url) {
             Ok(users) => {
                 if !users.is_empty() {
                     Ok(users[0].clone())
@@ -61,38 +224,27 @@ impl<'a> Gitter<'a> {
     }
 
     /// Returns a list of Rooms the user is part of
-    pub fn get_user_rooms<S>(&self, user_id: S) -> ApiResult<Vec<Room>>
-    where
-        S: AsRef<str>,
-    {
-        let full_url = format!("{}/user/{}/rooms", self.api_base_url, user_id.as_ref());
+    pub fn get_user_rooms(&self, user_id: &UserId) -> ApiResult<Vec<Room>> {
+        let full_url = format!("{}/user/{}/rooms", self.api_base_url, user_id);
         self.get(&full_url)
     }
 
     /// Receive user's unread items and mentions in the room
-    pub fn get_unread_items<U, R>(&self, user_id: U, room_id: R) -> ApiResult<UnreadItems>
-    where
-        U: AsRef<str>,
-        R: AsRef<str>,
-    {
-        let full_url = format!("{}/user/{}/rooms/{}/unreadItems", self.api_base_url, user_id.as_ref(), room_id.as_ref());
+    pub fn get_unread_items(&self, user_id: &UserId, room_id: &RoomId) -> ApiResult<UnreadItems> {
+        let full_url = format!("{}/user/{}/rooms/{}/unreadItems", self.api_base_url, user_id, room_id);
         self.get(&full_url)
     }
 
     /// Mark user's given room messages as read
-    pub fn mark_messages_as_read<U, R>(
+    pub fn mark_messages_as_read(
         &self,
-        user_id: U,
-        room_id: R,
-        message_ids: &[String],
-    ) -> ApiResult<()>
-    where
-        U: AsRef<str>,
-        R: AsRef<str>,
-    {
-        let full_url = format!("{}/user/{}/rooms/{}/unreadItems", self.api_base_url, user_id.as_ref(), room_id.as_ref());
+        user_id: &UserId,
+        room_id: &RoomId,
+        message_ids: &[MessageId],
+    ) -> ApiResult<()> {
+        let full_url = format!("{}/user/{}/rooms/{}/unreadItems", self.api_base_url, user_id, room_id);
         let unread_items = UnreadItems::from_msg_ids(message_ids);
-        self.post(&full_url, &unread_items)
+        self.post::<_, Confirmation>(&full_url, &unread_items, Expect::JsonOrEmpty).map(|_| ())
     }
 
     /// Returns a list of rooms the current user is in
@@ -102,57 +254,39 @@ impl<'a> Gitter<'a> {
     }
 
     /// List of the user's GitHub Organizations and their respective Room if available.
-    pub fn get_user_organizations<S>(&self, user_id: S) -> ApiResult<Vec<Organization>>
-    where
-        S: AsRef<str>,
-    {
-        let full_url = format!("{}/user/{}/orgs", self.api_base_url, user_id.as_ref());
+    pub fn get_user_organizations(&self, user_id: &UserId) -> ApiResult<Vec<Organization>> {
+        let full_url = format!("{}/user/{}/orgs", self.api_base_url, user_id);
         self.get(&full_url)
     }
 
     /// List of the user's GitHub Repositories and their respective Room if available.
-    pub fn get_user_repositories<S>(&self, user_id: S) -> ApiResult<Vec<Repository>>
-    where
-        S: AsRef<str>,
-    {
-        let full_url = format!("{}/user/{}/repos", self.api_base_url, user_id.as_ref());
+    pub fn get_user_repositories(&self, user_id: &UserId) -> ApiResult<Vec<Repository>> {
+        let full_url = format!("{}/user/{}/repos", self.api_base_url, user_id);
         self.get(&full_url)
     }
 
     /// List of Gitter channels nested under the current user.
-    pub fn get_user_channels<S>(&self, user_id: S) -> ApiResult<Vec<Channel>>
-    where
-        S: AsRef<str>,
-    {
-        let full_url = format!("{}/user/{}/channels", self.api_base_url, user_id.as_ref());
+    pub fn get_user_channels(&self, user_id: &UserId) -> ApiResult<Vec<Channel>> {
+        let full_url = format!("{}/user/{}/channels", self.api_base_url, user_id);
         self.get(&full_url)
     }
 
     /// Returns the users in the room with the passed id
-    pub fn get_users_in_room<S>(&self, room_id: S) -> ApiResult<Vec<User>>
-    where
-        S: AsRef<str>,
-    {
-        let full_url = format!("{}/rooms/{}/users", self.api_base_url, room_id.as_ref());
+    pub fn get_users_in_room(&self, room_id: &RoomId) -> ApiResult<Vec<User>> {
+        let full_url = format!("{}/rooms/{}/users", self.api_base_url, room_id);
         self.get(&full_url)
     }
 
     /// Returns a room with the passed id
-    pub fn get_room<S>(&self, room_id: S) -> ApiResult<Room>
-    where
-        S: AsRef<str>,
-    {
-        let full_url = format!("{}/rooms/{}", self.api_base_url, room_id.as_ref());
+    pub fn get_room(&self, room_id: &RoomId) -> ApiResult<Room> {
+        let full_url = format!("{}/rooms/{}", self.api_base_url, room_id);
         self.get(&full_url)
     }
 
     /// Returns a list of messages in a room.
     /// Pagination is optional. You can pass nil or specific pagination params.
-    pub fn get_messages<S>(&self, room_id: S, params: Option<Pagination>) -> ApiResult<Vec<Message>>
-    where
-        S: AsRef<str>,
-    {
-        let mut full_url = format!("{}/rooms/{}/chatMessages", self.api_base_url, room_id.as_ref());
+    pub fn get_messages(&self, room_id: &RoomId, params: Option<Pagination>) -> ApiResult<Vec<Message>> {
+        let mut full_url = format!("{}/rooms/{}/chatMessages", self.api_base_url, room_id);
 
         if let Some(p) = params {
             full_url.push_str("?");
@@ -163,121 +297,245 @@ impl<'a> Gitter<'a> {
     }
 
     /// Returns a message in a room.
-    pub fn get_message<R, M>(&self, room_id: R, message_id: M) -> ApiResult<Message>
-    where
-        R: AsRef<str>,
-        M: AsRef<str>,
-    {
-        let full_url = format!("{}/rooms/{}/chatMessages/{}", self.api_base_url, room_id.as_ref(), message_id.as_ref());
+    pub fn get_message(&self, room_id: &RoomId, message_id: &MessageId) -> ApiResult<Message> {
+        let full_url = format!("{}/rooms/{}/chatMessages/{}", self.api_base_url, room_id, message_id);
+        self.get(&full_url)
+    }
+
+    /// Returns a list of users that have read the message
+    pub fn get_message_read_by(&self, room_id: &RoomId, message_id: &MessageId) -> ApiResult<Vec<User>> {
+        let full_url = format!("{}/rooms/{}/chatMessages/{}/readBy", self.api_base_url, room_id, message_id);
         self.get(&full_url)
     }
 
     /// Sends a message to a room
-    pub fn send_message<R, T>(&self, room_id: R, text: T) -> ApiResult<Message>
+    pub fn send_message<T>(&self, room_id: &RoomId, text: T) -> ApiResult<Message>
     where
-        R: AsRef<str>,
         T: AsRef<str>,
     {
-        let full_url = format!("{}/rooms/{}/chatMessages", self.api_base_url, room_id.as_ref());
+        let full_url = format!("{}/rooms/{}/chatMessages", self.api_base_url, room_id);
         let msg = OutMessage {
-            text: text.as_ref()
+            text: text.as_ref(),
+            parent_id: None,
         };
 
-        self.post(&full_url, &msg)
+        let _permit = self.acquire_send(room_id)?;
+        self.post(&full_url, &msg, Expect::Json)
     }
 
-    /// Update a message
-    pub fn update_message<R, M, T>(&self, room_id: R, msg_id: M, text: T) -> ApiResult<()>
+    /// Sends a message to a room, retrying on failures according to the retry policy.
+    ///
+    /// `idempotency_key` must be unique for each message and the same for all retries
+    /// of one message. It is sent in the `Idempotency-Key` header, so a server or
+    /// a proxy which supports it can drop duplicates. Without such support a retry
+    /// after a lost response may post the message twice
+    pub fn send_message_with_idempotency_key<T, K>(&self, room_id: &RoomId, text: T, idempotency_key: K) -> ApiResult<Message>
     where
-        R: AsRef<str>,
-        M: AsRef<str>,
         T: AsRef<str>,
+        K: AsRef<str>,
     {
-        let full_url = format!("{}/rooms/{}/chatMessages/{}", self.api_base_url, room_id.as_ref(), msg_id.as_ref());
+        let full_url = format!("{}/rooms/{}/chatMessages", self.api_base_url, room_id);
         let msg = OutMessage {
-            text: text.as_ref()
+            text: text.as_ref(),
+            parent_id: None,
         };
 
-        self.put(&full_url, &msg)
+        let _permit = self.acquire_send(room_id)?;
+        self.post_idempotent(&full_url, &msg, idempotency_key.as_ref())
     }
 
-    /// Joins a room
-    pub fn join_room<R, U>(&self, room_id: R, user_id: U) -> ApiResult<Room>
+    /// Sends a message to the thread of `parent_id`
+    pub fn send_thread_reply<T>(&self, room_id: &RoomId, parent_id: &MessageId, text: T) -> ApiResult<Message>
     where
-        R: AsRef<str>,
-        U: AsRef<str>,
+        T: AsRef<str>,
     {
-        let full_url = format!("{}/user/{}/rooms", self.api_base_url, user_id.as_ref());
-        let room = JoinRoom::from_id(room_id);
+        let full_url = format!("{}/rooms/{}/chatMessages", self.api_base_url, room_id);
+        let msg = OutMessage {
+            text: text.as_ref(),
+            parent_id: Some(parent_id),
+        };
 
-        self.post(&full_url, &room)
+        let _permit = self.acquire_send(room_id)?;
+        self.post(&full_url, &msg, Expect::Json)
     }
 
-    /// Join a room (uri method)
-    pub fn join_room_by_uri<S>(&self, uri: S) -> ApiResult<Room>
+    /// Sends text of any length as several messages, see `split_message`.
+    ///
+    /// Messages are sent in order and returned in order, other sends to the
+    /// room wait until all of them are sent. With `in_thread` the rest are sent
+    /// as replies in the thread of the first message. Blank text sends nothing.
+    /// Stops at the first failure, the messages sent before it stay in the room
+    pub fn send_long_message<T>(&self, room_id: &RoomId, text: T, in_thread: bool) -> ApiResult<Vec<Message>>
     where
-        S: AsRef<str>,
+        T: AsRef<str>,
     {
-        let full_url = format!("{}/rooms", self.api_base_url);
-        let room = JoinRoom::from_uri(uri);
+        let chunks = split_message(text.as_ref(), MAX_MESSAGE_LENGTH);
+        if chunks.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let full_url = format!("{}/rooms/{}/chatMessages", self.api_base_url, room_id);
+        let mut messages: Vec<Message> = Vec::with_capacity(chunks.len());
+        let mut parent_id: Option<MessageId> = None;
+
+        // One turn for all chunks, every chunk still counts against the limits
+        let permit = self.acquire_send(room_id)?;
+
+        for (i, chunk) in chunks.iter().enumerate() {
+            if let Some(ref permit) = permit {
+                if i > 0 {
+                    permit.take().map_err(ApiError::RateLimited)?;
+                }
+            }
+
+            let msg = OutMessage {
+                text: chunk,
+                parent_id: parent_id.as_ref(),
+            };
+            let message: Message = self.post(&full_url, &msg, Expect::Json)?;
+
+            if in_thread && parent_id.is_none() {
+                parent_id = Some(message.id.clone());
+            }
+            messages.push(message);
+        }
 
-        self.post(&full_url, &room)
+        Ok(messages)
     }
 
-    /// Update a room topic
-    pub fn update_room_topic<R, T>(&self, room_id: R, topic: T) -> ApiResult<Room>
+    /// Update a message
+    pub fn update_message<T>(&self, room_id: &RoomId, msg_id: &MessageId, text: T) -> ApiResult<()>
     where
-        R: AsRef<str>,
         T: AsRef<str>,
     {
-        let full_url = format!("{}/rooms/{}", self.api_base_url, room_id.as_ref());
-        let room = UpdateRoom::from_topic(topic);
+        let full_url = format!("{}/rooms/{}/chatMessages/{}", self.api_base_url, room_id, msg_id);
+        let msg = OutMessage {
+            text: text.as_ref(),
+            parent_id: None,
+        };
 
-        self.post(&full_url, &room)
+        self.put(&full_url, &msg, Expect::Empty)
     }
 
-    /// Update a room noindex (indexing in search engines)
-    pub fn update_room_noindex<S>(&self, room_id: S, noindex: bool) -> ApiResult<Room>
-    where
-        S: AsRef<str>,
-    {
-        let full_url = format!("{}/rooms/{}", self.api_base_url, room_id.as_ref());
-        let room = UpdateRoom::from_noindex(noindex);
+    /// Joins a room
+    pub fn join_room(&self, room_id: &RoomId, user_id: &UserId) -> ApiResult<Room> {
+        let full_url = format!("{}/user/{}/rooms", self.api_base_url, user_id);
+        let room = JoinRoom::from_id(room_id);
+
+        self.post(&full_url, &room, Expect::Json)
+    }
+
+    /// Join a room (uri method)
+    pub fn join_room_by_uri(&self, uri: &RoomUri) -> ApiResult<Room> {
+        let full_url = format!("{}/rooms", self.api_base_url);
+        let room = JoinRoom::from_uri(uri);
 
-        self.post(&full_url, &room)
+        self.post(&full_url, &room, Expect::Json)
+    }
+
+    /// Update room settings. Only the fields set in `room` are changed
+    pub fn update_room(&self, room_id: &RoomId, room: UpdateRoom) -> ApiResult<Room> {
+        let full_url = format!("{}/rooms/{}", self.api_base_url, room_id);
+
+        self.put(&full_url, &room, Expect::Json)
     }
 
     /// Update a room topic
-    pub fn update_room_tags<R, T>(&self, room_id: R, tags: T) -> ApiResult<Room>
+    pub fn update_room_topic<T>(&self, room_id: &RoomId, topic: T) -> ApiResult<Room>
     where
-        R: AsRef<str>,
         T: AsRef<str>,
     {
-        let full_url = format!("{}/rooms/{}", self.api_base_url, room_id.as_ref());
-        let room = UpdateRoom::from_tags(tags);
+        self.update_room(room_id, UpdateRoom::from_topic(topic))
+    }
 
-        self.post(&full_url, &room)
+    /// Update a room noindex (indexing in search engines)
+    pub fn update_room_noindex(&self, room_id: &RoomId, noindex: bool) -> ApiResult<Room> {
+        self.update_room(room_id, UpdateRoom::from_noindex(noindex))
     }
 
-    /// Removes a user from the room
-    pub fn leave_room<R, U>(&self, room_id: R, user_id: U) -> ApiResult<()>
+    /// Update a room tags (comma separated)
+    pub fn update_room_tags<T>(&self, room_id: &RoomId, tags: T) -> ApiResult<Room>
     where
-        R: AsRef<str>,
-        U: AsRef<str>,
+        T: AsRef<str>,
     {
-        let full_url = format!("{}/rooms/{}/users/{}", self.api_base_url, room_id.as_ref(), user_id.as_ref());
+        self.update_room(room_id, UpdateRoom::from_tags(tags))
+    }
+
+    /// Removes a user from the room.
+    ///
+    /// Use it with the current user's ID to leave the room.
+    /// To remove somebody else use `remove_user_from_room`
+    pub fn leave_room(&self, room_id: &RoomId, user_id: &UserId) -> ApiResult<()> {
+        let full_url = format!("{}/rooms/{}/users/{}", self.api_base_url, room_id, user_id);
+
+        self.delete::<Confirmation>(&full_url, Expect::JsonOrEmpty).map(|_| ())
+    }
+
+    /// Removes another user from the room. Requires room admin rights:
+    /// fails with `PermissionDenied` before sending the request if the current
+    /// user is known not to have them, and when the server refuses otherwise
+    pub fn remove_user_from_room(&self, room_id: &RoomId, user_id: &UserId) -> ApiResult<()> {
+        if self.get_room_permissions(room_id)?.is_admin() == Some(false) {
+            return Err(ApiError::PermissionDenied(
+                "removing users from the room requires admin rights".to_string(),
+            ));
+        }
+
+        self.leave_room(room_id, user_id)
+    }
+
+    /// Returns the current user's role in the room
+    /// and the security descriptor it is derived from.
+    ///
+    /// Gitter reports admin rights in the room's permissions. Responses without
+    /// them fall back to the descriptor: the owner of the GitHub account backing
+    /// the room's group administers the room. Admins of GitHub organizations and
+    /// repositories, and extra admins, are not visible, so the role is `None` then
+    pub fn get_room_permissions(&self, room_id: &RoomId) -> ApiResult<RoomPermissions> {
+        let room = self.get_room(room_id)?;
+
+        let backed_by = match room.group_id {
+            Some(ref group_id) => Some(self.get_group(group_id)?.backed_by),
+            None => None,
+        };
+
+        let is_admin = match (room.permissions.as_ref(), backed_by.as_ref()) {
+            (Some(permissions), _) => Some(permissions.admin),
+            (None, Some(&BackedBy { group_type: Some(GroupType::GhUser), link_path: Some(ref link_path), .. })) => {
+                if self.get_user()?.username.eq_ignore_ascii_case(link_path) {
+                    Some(true)
+                } else {
+                    None
+                }
+            }
+            (None, _) => None,
+        };
 
-        self.delete(&full_url)
+        let role = is_admin.map(|is_admin| {
+            if is_admin {
+                RoomRole::Admin
+            } else if room.room_member {
+                RoomRole::Member
+            } else {
+                RoomRole::Guest
+            }
+        });
+
+        Ok(RoomPermissions {
+            role,
+            member: room.room_member,
+            backed_by,
+        })
     }
 
     /// Delete a room
-    pub fn delete_room<S>(&self, room_id: S) -> ApiResult<()>
-    where
-        S: AsRef<str>,
-    {
-        let full_url = format!("{}/rooms/{}", self.api_base_url, room_id.as_ref());
+    pub fn delete_room(&self, room_id: &RoomId) -> ApiResult<()> {
+        let full_url = format!("{}/rooms/{}", self.api_base_url, room_id);
 
-        self.delete(&full_url)
+        self.delete::<Confirmation>(&full_url, Expect::JsonOrEmpty)?;
+        self.room_ids.lock().unwrap().retain(|_, cached| cached.0 != *room_id);
+
+        Ok(())
     }
 
     /// Queries the Rooms resources of gitter API
@@ -291,18 +549,66 @@ impl<'a> Gitter<'a> {
         self.get(&full_url)
     }
 
-    /// Returns the room ID of a given URI
-    pub fn get_room_id<S>(&self, uri: S) -> ApiResult<String>
-    where
-        S: AsRef<str>,
-    {
-        let uri = uri.as_ref();
-        self.search_rooms(uri.to_string())?
-            .rooms
-            .iter()
-            .find(|r| r.uri.as_ref().map_or(false, |u| u == uri))
-            .map(|r| r.id.to_string())
-            .ok_or(ApiError::RoomNotFound)
+    /// Returns the room ID of a given URI.
+    ///
+    /// Looks for the room among the current user's rooms first. Other rooms
+    /// are looked up by their exact URI with `POST /rooms`, which finds private,
+    /// low ranked and unindexed rooms the room search misses. Gitter adds the
+    /// current user to a room resolved this way, as with `join_room_by_uri`.
+    ///
+    /// Fails with `RoomNotFound` if there is no such room, `PermissionDenied`
+    /// if the user has no access to it, and `AmbiguousRoom` if the URI matches
+    /// several of the user's rooms, e.g. an organization and a one to one room
+    /// with a user of the same name. Resolved IDs are cached, see `with_room_id_ttl`
+    pub fn get_room_id(&self, uri: &RoomUri) -> ApiResult<RoomId> {
+        if let Some(room_id) = self.cached_room_id(uri) {
+            return Ok(room_id);
+        }
+
+        let mut room_ids = matching_room_ids(self.get_rooms()?, uri);
+        let room_id = match room_ids.len() {
+            0 => match self.join_room_by_uri(uri) {
+                Ok(room) => room.id,
+                Err(ApiError::NotFound(_)) => return Err(ApiError::RoomNotFound),
+                Err(e) => return Err(e),
+            },
+            1 => room_ids.remove(0),
+            _ => return Err(ApiError::AmbiguousRoom(room_ids)),
+        };
+
+        if self.room_id_ttl > Duration::from_secs(0) {
+            let expires_at = Instant::now() + self.room_id_ttl;
+            self.room_ids.lock().unwrap().insert(uri.clone(), (room_id.clone(), expires_at));
+        }
+
+        Ok(room_id)
+    }
+
+    /// Waits for the send limits, the permit keeps the next send
+    /// to the room waiting until this one is done
+    fn acquire_send(&self, room_id: &RoomId) -> ApiResult<Option<Permit<'_>>> {
+        match self.send_limiter {
+            Some(ref limiter) => limiter.acquire(room_id).map(Some).map_err(ApiError::RateLimited),
+            None => Ok(None),
+        }
+    }
+
+    /// Forgets all room IDs resolved by `get_room_id`
+    pub fn clear_room_id_cache(&self) {
+        self.room_ids.lock().unwrap().clear();
+    }
+
+    fn cached_room_id(&self, uri: &RoomUri) -> Option<RoomId> {
+        let mut room_ids = self.room_ids.lock().unwrap();
+
+        match room_ids.get(uri) {
+            Some(&(ref room_id, expires_at)) if expires_at > Instant::now() => return Some(room_id.clone()),
+            Some(_) => {}
+            None => return None,
+        }
+
+        room_ids.remove(uri);
+        None
     }
 
     /// Returns a list of groups the current user is in
@@ -312,100 +618,419 @@ impl<'a> Gitter<'a> {
         self.get(&full_url)
     }
 
+    /// Returns a group with the passed id
+    pub fn get_group(&self, group_id: &GroupId) -> ApiResult<Group> {
+        let full_url = format!("{}/groups/{}", self.api_base_url, group_id);
+
+        self.get(&full_url)
+    }
+
     /// List of rooms nested under the specified group.
-    pub fn get_group_rooms<S>(&self, group_id: S) -> ApiResult<Vec<Room>>
-    where
-        S: AsRef<str>,
-    {
-        let full_url = format!("{}/groups/{}/rooms", self.api_base_url, group_id.as_ref());
+    pub fn get_group_rooms(&self, group_id: &GroupId) -> ApiResult<Vec<Room>> {
+        let full_url = format!("{}/groups/{}/rooms", self.api_base_url, group_id);
 
         self.get(&full_url)
     }
 
-    /// create default headers
-    fn default_headers(&self) -> Headers {
-        let mut headers = Headers::new();
+    /// create default headers with the current token
+    fn default_headers(&self) -> ApiResult<Vec<(String, String)>> {
+        let mut authorization = String::new();
+        self.credentials
+            .with_token(&mut |token| authorization = format!("Bearer {}", token))
+            .map_err(ApiError::Credentials)?;
 
-        headers.set(ContentType::json());
-        headers.set(Accept::json());
-        headers.set(Authorization(Bearer {
-            token: self.token.to_string(),
-        }));
+        Ok(vec![
+            ("Content-Type".to_string(), "application/json".to_string()),
+            ("Accept".to_string(), "application/json".to_string()),
+            ("Authorization".to_string(), authorization),
+        ])
+    }
+
+    /// Creates request with the default headers
+    fn request(&self, method: HttpMethod, url: &str) -> ApiResult<HttpRequest> {
+        Ok(HttpRequest {
+            method,
+            url: url.to_string(),
+            headers: self.default_headers()?,
+            body: None,
+        })
+    }
 
-        headers
+    /// Checks response status and deserializes response body
+    fn read_response<T>(response: HttpResponse, expect: Expect) -> ApiResult<T>
+    where
+        for<'de> T: Deserialize<'de>,
+    {
+        match response.status {
+            401 | 403 => Err(ApiError::PermissionDenied(response.body)),
+            404 => Err(ApiError::NotFound(response.body)),
+            status if !response.is_success() => {
+                Err(ApiError::BadResponse(format!("unexpected status {}: {}", status, response.body)))
+            }
+            _ => Self::parse_body(&response.body, expect),
+        }
     }
 
     /// Returns raw data in bytes from specified url
-    fn get<S, T>(&self, url: S) -> ApiResult<T>
+    fn get<T>(&self, url: &str) -> ApiResult<T>
+    where
+        for<'de> T: Deserialize<'de>,
+    {
+        let mut request = self.request(HttpMethod::Get, url)?;
+
+        let cache = match self.cache {
+            Some(ref cache) => cache,
+            None => return Self::read_response(self.send(request, false)?, Expect::Json),
+        };
+
+        if let Some(body) = cache.fresh(url) {
+            return Self::parse_body(&body, Expect::Json);
+        }
+
+        let mut generation = cache.generation();
+
+        if let Some(validators) = cache.validators(url) {
+            if let Some(etag) = validators.etag {
+                request.set_header("If-None-Match", etag);
+            }
+            if let Some(last_modified) = validators.last_modified {
+                request.set_header("If-Modified-Since", last_modified);
+            }
+        }
+
+        let mut response = self.send(request, false)?;
+
+        if response.status == 304 {
+            if let Some(body) = cache.revalidate(url) {
+                return Self::parse_body(&body, Expect::Json);
+            }
+
+            // The entry was invalidated while the request was sent
+            generation = cache.generation();
+            response = self.send(self.request(HttpMethod::Get, url)?, false)?;
+        }
+
+        cache.miss();
+
+        if !response.is_success() {
+            return Self::read_response(response, Expect::Json);
+        }
+
+        let result = Self::parse_body(&response.body, Expect::Json);
+
+        if result.is_ok() {
+            let validators = Validators {
+                etag: response.header("ETag").map(str::to_string),
+                last_modified: response.header("Last-Modified").map(str::to_string),
+            };
+            cache.store(url, response.body, validators, generation);
+        }
+
+        result
+    }
+
+    /// Deserializes response body. Empty and ignored bodies are deserialized
+    /// from `null`, which gives `()` or `None`
+    fn parse_body<T>(body: &str, expect: Expect) -> ApiResult<T>
     where
-        S: IntoUrl,
         for<'de> T: Deserialize<'de>,
     {
-        match self.client.get(url).headers(self.default_headers()).send() {
-            Ok(mut response) => response
-                .json::<T>()
-                .map_err(|e| ApiError::BadResponse(e.to_string())),
-            Err(e) => Err(ApiError::BadRequest(e.to_string())),
+        let body = match expect {
+            Expect::Json if body.trim().is_empty() => return Err(ApiError::EmptyResponse),
+            Expect::Empty => "null",
+            Expect::JsonOrEmpty if body.trim().is_empty() => "null",
+            _ => body,
+        };
+
+        serde_json::from_str(body).map_err(|e| ApiError::BadResponse(e.to_string()))
+    }
+
+    /// Drops cached responses the mutating request to `url` may change
+    fn invalidate_cache(&self, url: &str) {
+        if let Some(ref cache) = self.cache {
+            cache.invalidate(url);
         }
     }
 
-    /// Posts raw body data to specified url and returns response raw data
-    fn post<S, B, T>(&self, url: S, body: B) -> ApiResult<T>
+    /// Posts raw body data to specified url and returns response raw data.
+    /// POST is not idempotent, so it is never retried
+    fn post<B, T>(&self, url: &str, body: B, expect: Expect) -> ApiResult<T>
     where
-        S: IntoUrl,
         B: Serialize,
         for<'de> T: Deserialize<'de>,
     {
-        match self.client
-            .post(url)
-            .headers(self.default_headers())
-            .json(&body)
-            .send()
-        {
-            Ok(mut response) => response
-                .json::<T>()
-                .map_err(|e| ApiError::BadResponse(e.to_string())),
-            Err(e) => Err(ApiError::BadRequest(e.to_string())),
-        }
+        let mut request = self.request(HttpMethod::Post, url)?;
+        request.body = Some(Self::to_json(body)?);
+
+        self.mutate(request, false, expect)
+    }
+
+    /// Posts raw body data with an idempotency key, which makes retrying it safe
+    fn post_idempotent<B, T>(&self, url: &str, body: B, idempotency_key: &str) -> ApiResult<T>
+    where
+        B: Serialize,
+        for<'de> T: Deserialize<'de>,
+    {
+        let mut request = self.request(HttpMethod::Post, url)?;
+        request.body = Some(Self::to_json(body)?);
+        request.set_header("Idempotency-Key", idempotency_key);
+
+        self.mutate(request, true, Expect::Json)
     }
 
     /// Puts raw body data to specified url and returns response raw data
-    fn put<S, B, T>(&self, url: S, body: B) -> ApiResult<T>
+    fn put<B, T>(&self, url: &str, body: B, expect: Expect) -> ApiResult<T>
     where
-        S: IntoUrl,
         B: Serialize,
         for<'de> T: Deserialize<'de>,
     {
-        match self.client
-            .put(url)
-            .headers(self.default_headers())
-            .json(&body)
-            .send()
-        {
-            Ok(mut response) => response
-                .json::<T>()
-                .map_err(|e| ApiError::BadResponse(e.to_string())),
-            Err(e) => Err(ApiError::BadRequest(e.to_string())),
-        }
+        let mut request = self.request(HttpMethod::Put, url)?;
+        request.body = Some(Self::to_json(body)?);
+
+        self.mutate(request, false, expect)
     }
 
     /// Deletes resource by specified url
-    fn delete<S, T>(&self, url: S) -> ApiResult<T>
+    fn delete<T>(&self, url: &str, expect: Expect) -> ApiResult<T>
+    where
+        for<'de> T: Deserialize<'de>,
+    {
+        let request = self.request(HttpMethod::Delete, url)?;
+
+        self.mutate(request, false, expect)
+    }
+
+    /// Sends a mutating request and drops the cached responses it changed.
+    /// They are dropped once the server has applied the change,
+    /// so a concurrent GET cannot cache the old state again
+    fn mutate<T>(&self, request: HttpRequest, retry: bool, expect: Expect) -> ApiResult<T>
     where
-        S: IntoUrl,
         for<'de> T: Deserialize<'de>,
     {
-        match self.client
-            .delete(url)
-            .headers(self.default_headers())
-            .send()
-        {
-            Ok(mut response) => response
-                .json::<T>()
-                .map_err(|e| ApiError::BadResponse(e.to_string())),
-            Err(e) => Err(ApiError::BadRequest(e.to_string())),
+        let url = request.url.clone();
+        let response = self.send(request, retry)?;
+
+        if response.is_success() {
+            self.invalidate_cache(&url);
+        }
+
+        Self::read_response(response, expect)
+    }
+
+    fn to_json<B>(body: B) -> ApiResult<String>
+    where
+        B: Serialize,
+    {
+        serde_json::to_string(&body).map_err(|e| ApiError::BadRequest(e.to_string()))
+    }
+
+    /// Passes request through the middlewares and sends it.
+    /// `retry` retries the request even if the retry policy does not retry its method
+    fn send(&self, request: HttpRequest, retry: bool) -> ApiResult<HttpResponse> {
+        let span = CallSpan::start(&request, request.url.trim_start_matches(self.api_base_url.as_str()));
+
+        let result = {
+            let transport = |request: HttpRequest| {
+                let (result, retries) = self.send_with_retries(&request, retry);
+                span.record_retries(retries);
+                result
+            };
+
+            span.in_scope(|| Next::new(&self.middlewares, &transport).run(request))
+        };
+
+        span.finish(&result);
+        result
+    }
+
+    /// Sends request. Connection errors and retryable statuses are retried
+    /// according to the retry policy if it retries the method, or if `retry`
+    /// is set. Returns the result of the last attempt and the number of retries
+    fn send_with_retries(&self, request: &HttpRequest, retry: bool) -> (ApiResult<HttpResponse>, u32) {
+        let retry = retry || self.retry_policy.is_retryable_method(request.method);
+        let max_attempts = if retry { self.retry_policy.attempts() } else { 1 };
+        let mut attempt = 1;
+
+        loop {
+            let mut result = self.send_once(request);
+
+            // An earlier attempt may have deleted the resource and lost the response
+            if attempt > 1 && request.method == HttpMethod::Delete {
+                if let Ok(ref mut response) = result {
+                    if response.status == 404 {
+                        *response = HttpResponse::new(204, "");
+                    }
+                }
+            }
+
+            if attempt >= max_attempts {
+                return (result, attempt - 1);
+            }
+
+            let delay = match result {
+                Ok(ref response) if self.retry_policy.is_retryable_status(response.status) => {
+                    self.retry_policy.delay(attempt, retry_after(response))
+                }
+                Err(ApiError::BadRequest(_)) => self.retry_policy.delay(attempt, None),
+                result => return (result, attempt - 1),
+            };
+
+            thread::sleep(delay);
+            attempt += 1;
+        }
+    }
+
+    /// Sends request once and reads the whole response
+    fn send_once(&self, request: &HttpRequest) -> ApiResult<HttpResponse> {
+        let method = match request.method {
+            HttpMethod::Get => Method::Get,
+            HttpMethod::Post => Method::Post,
+            HttpMethod::Put => Method::Put,
+            HttpMethod::Delete => Method::Delete,
+        };
+
+        let mut headers = Headers::new();
+        for (name, value) in &request.headers {
+            headers.set_raw(name.clone(), value.clone());
+        }
+
+        let mut builder = self.client.request(method, request.url.as_str());
+        builder.headers(headers);
+        if let Some(ref body) = request.body {
+            builder.body(body.clone());
+        }
+
+        let mut response = builder.send().map_err(|e| ApiError::BadRequest(e.to_string()))?;
+
+        let headers = response
+            .headers()
+            .iter()
+            .map(|header| (header.name().to_string(), header.value_string()))
+            .collect();
+        let body = response.text().map_err(|e| ApiError::BadResponse(e.to_string()))?;
+
+        Ok(HttpResponse {
+            status: response.status().as_u16(),
+            headers,
+            body,
+        })
+    }
+}
+
+/// What the body of a successful response is expected to be
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Expect {
+    /// JSON, an empty body is `ApiError::EmptyResponse`
+    Json,
+    /// Nothing of interest, the body is ignored
+    Empty,
+    /// JSON or nothing, e.g. `{"success":true}` or `204 No Content`
+    JsonOrEmpty,
+}
+
+/// Creates `Gitter` with custom HTTP client settings:
+///
+/// ```rust,ignore
+/// let api = Gitter::builder(token)
+///     .timeout(Duration::from_secs(10))
+///     .tls(TlsConfig::new().ca_bundle_pem(corp_ca))
+///     .build()?;
+/// ```
+pub struct GitterBuilder {
+    credentials: Arc<dyn CredentialProvider>,
+    client: Option<Client>,
+    timeout: Duration,
+    tls: TlsConfig,
+}
+
+impl fmt::Debug for GitterBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("GitterBuilder")
+            .field("client", &self.client.is_some())
+            .field("timeout", &self.timeout)
+            .field("tls", &self.tls)
+            .finish()
+    }
+}
+
+impl GitterBuilder {
+    /// Creates builder of the client which takes the token from `credentials`
+    pub fn from_credentials<P>(credentials: P) -> GitterBuilder
+    where
+        P: CredentialProvider + 'static,
+    {
+        GitterBuilder {
+            credentials: Arc::new(credentials),
+            client: None,
+            timeout: TIMEOUT,
+            tls: TlsConfig::new(),
         }
     }
+
+    /// Sets the timeout of requests, 40 seconds by default
+    pub fn timeout(mut self, timeout: Duration) -> GitterBuilder {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Sets custom CA certificates and the client certificate
+    pub fn tls(mut self, tls: TlsConfig) -> GitterBuilder {
+        self.tls = tls;
+        self
+    }
+
+    /// Sends requests through an existing HTTP client.
+    /// The timeout and TLS settings of the builder are ignored then
+    pub fn client(mut self, client: Client) -> GitterBuilder {
+        self.client = Some(client);
+        self
+    }
+
+    pub fn build(self) -> ApiResult<Gitter> {
+        let client = match self.client {
+            Some(client) => client,
+            None => build_client(self.timeout, &self.tls)?,
+        };
+
+        Ok(Gitter::from_parts(self.credentials, client))
+    }
+}
+
+/// Creates an HTTP client with the timeout and TLS settings
+pub(crate) fn build_client(timeout: Duration, tls: &TlsConfig) -> ApiResult<Client> {
+    let mut builder = Client::builder();
+    builder.timeout(timeout);
+    tls.apply(&mut builder).map_err(ApiError::Unknown)?;
+    builder.build().map_err(|e| ApiError::Unknown(e.to_string()))
+}
+
+/// Returns the delay the server asked for in the `Retry-After` header, in seconds
+fn retry_after(response: &HttpResponse) -> Option<Duration> {
+    response
+        .header("Retry-After")
+        .and_then(|value| value.trim().parse().ok())
+        .map(Duration::from_secs)
+}
+
+/// Returns the distinct IDs of the rooms with the URI
+fn matching_room_ids(rooms: Vec<Room>, uri: &RoomUri) -> Vec<RoomId> {
+    let mut room_ids = rooms
+        .into_iter()
+        .filter(|room| room_matches_uri(room, uri))
+        .map(|room| room.id)
+        .collect::<Vec<_>>();
+    room_ids.sort();
+    room_ids.dedup();
+    room_ids
+}
+
+/// One to one rooms have no URI, their URL is the other user's name
+fn room_matches_uri(room: &Room, uri: &RoomUri) -> bool {
+    if room.one_to_one {
+        room.url.trim_matches('/').eq_ignore_ascii_case(uri.as_str())
+    } else {
+        !uri.is_user() && room.uri.as_ref().map_or(false, |u| uri.matches(u))
+    }
 }
 
 /// Pagination params
@@ -414,10 +1039,10 @@ pub struct Pagination<'a> {
     pub skip: i32,
 
     /// Get messages before beforeId
-    pub before_id: Option<Cow<'a, str>>,
+    pub before_id: Option<MessageId>,
 
     /// Get messages after afterId
-    pub after_id: Option<Cow<'a, str>>,
+    pub after_id: Option<MessageId>,
 
     /// Maximum number of messages to return
     pub limit: i32,
@@ -0,0 +1,124 @@
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+use chrono::{DateTime, TimeZone, Utc};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde::de;
+
+/// Length of the hex encoded Mongo ObjectId
+const OBJECT_ID_LEN: usize = 24;
+
+/// Error returned when a string is not a valid Gitter ID
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseIdError {
+    id: String,
+}
+
+impl fmt::Display for ParseIdError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid Gitter ID {:?}: expected {} hex digits", self.id, OBJECT_ID_LEN)
+    }
+}
+
+impl Error for ParseIdError {
+    fn description(&self) -> &str {
+        "invalid Gitter ID"
+    }
+}
+
+/// Gitter IDs are Mongo ObjectIds: 12 bytes in hex,
+/// starting with the creation time in seconds
+fn is_object_id(id: &str) -> bool {
+    id.len() == OBJECT_ID_LEN && id.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+macro_rules! object_id {
+    ($(#[$attr:meta])* $name:ident) => {
+        $(#[$attr])*
+        #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+        pub struct $name(String);
+
+        impl $name {
+            /// Creates ID from its hex representation
+            pub fn new<S>(id: S) -> Result<$name, ParseIdError>
+                where S: Into<String>
+            {
+                let id = id.into();
+
+                if is_object_id(&id) {
+                    Ok($name(id.to_lowercase()))
+                } else {
+                    Err(ParseIdError { id })
+                }
+            }
+
+            /// Returns ID hex representation
+            pub fn as_str(&self) -> &str {
+                &self.0
+            }
+
+            /// Returns creation time encoded in the ID
+            pub fn timestamp(&self) -> DateTime<Utc> {
+                let secs = i64::from_str_radix(&self.0[..8], 16).unwrap();
+                Utc.timestamp_opt(secs, 0).unwrap()
+            }
+        }
+
+        impl FromStr for $name {
+            type Err = ParseIdError;
+
+            fn from_str(s: &str) -> Result<$name, ParseIdError> {
+                $name::new(s)
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str(&self.0)
+            }
+        }
+
+        impl AsRef<str> for $name {
+            fn as_ref(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl Serialize for $name {
+            fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+                where S: Serializer
+            {
+                serializer.serialize_str(&self.0)
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $name {
+            fn deserialize<D>(deserializer: D) -> Result<$name, D::Error>
+                where D: Deserializer<'de>
+            {
+                let id = String::deserialize(deserializer)?;
+                $name::new(id).map_err(de::Error::custom)
+            }
+        }
+    };
+}
+
+object_id! {
+    /// Gitter room ID
+    RoomId
+}
+
+object_id! {
+    /// Gitter user ID
+    UserId
+}
+
+object_id! {
+    /// Gitter chat message ID
+    MessageId
+}
+
+object_id! {
+    /// Gitter group (community) ID
+    GroupId
+}
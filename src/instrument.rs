@@ -0,0 +1,144 @@
+use std::time::Instant;
+
+#[cfg(feature = "tracing")]
+use tracing::field::Empty;
+#[cfg(feature = "tracing")]
+use tracing::Span;
+
+use gitter::ApiError;
+use ids::*;
+use middleware::*;
+#[cfg(feature = "tracing")]
+use retry::millis;
+
+/// Placeholder which replaces IDs in path templates
+const ID_PLACEHOLDER: &str = "{id}";
+
+/// Headers whose values are never written anywhere
+const SECRET_HEADERS: &[&str] = &["Authorization", "Cookie", "Set-Cookie"];
+
+/// Replaces IDs in the API path with `{id}` and drops the query,
+/// e.g. `/rooms/{id}/chatMessages`. Templates group calls
+/// to the same endpoint in logs and metrics
+pub fn path_template(path: &str) -> String {
+    let path = path.split('?').next().unwrap_or("");
+
+    path.split('/')
+        .map(|segment| if RoomId::new(segment).is_ok() { ID_PLACEHOLDER } else { segment })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Returns the room ID the API path refers to, if any
+pub fn path_room_id(path: &str) -> Option<RoomId> {
+    let path = path.split('?').next().unwrap_or("");
+    let mut segments = path.split('/');
+
+    while let Some(segment) = segments.next() {
+        if segment == "rooms" {
+            return segments.next().and_then(|id| RoomId::new(id).ok());
+        }
+    }
+
+    None
+}
+
+/// Returns headers with the values of secret headers, like the token, replaced
+pub fn redact_headers(headers: &[(String, String)]) -> Vec<(String, String)> {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            if SECRET_HEADERS.iter().any(|secret| secret.eq_ignore_ascii_case(name)) {
+                (name.clone(), "[redacted]".to_string())
+            } else {
+                (name.clone(), value.clone())
+            }
+        })
+        .collect()
+}
+
+/// Span of one API call. Does nothing unless the `tracing` feature is enabled
+pub(crate) struct CallSpan {
+    #[cfg(feature = "tracing")]
+    span: Span,
+    #[allow(dead_code)]
+    started_at: Instant,
+}
+
+impl CallSpan {
+    /// Starts span of the request, `path` is the URL relative to the API base URL
+    #[cfg(feature = "tracing")]
+    pub fn start(request: &HttpRequest, path: &str) -> CallSpan {
+        let span = info_span!(
+            "gitter_request",
+            method = request.method.as_str(),
+            path = path_template(path).as_str(),
+            room_id = Empty,
+            status = Empty,
+            latency_ms = Empty,
+            retries = Empty
+        );
+
+        if let Some(room_id) = path_room_id(path) {
+            span.record("room_id", room_id.as_str());
+        }
+
+        span.in_scope(|| trace!(headers = ?redact_headers(&request.headers), "sending request"));
+
+        CallSpan {
+            span,
+            started_at: Instant::now(),
+        }
+    }
+
+    #[cfg(not(feature = "tracing"))]
+    pub fn start(_request: &HttpRequest, _path: &str) -> CallSpan {
+        CallSpan {
+            started_at: Instant::now(),
+        }
+    }
+
+    /// Runs `f` inside the span
+    #[cfg(feature = "tracing")]
+    pub fn in_scope<F, T>(&self, f: F) -> T
+        where F: FnOnce() -> T
+    {
+        self.span.in_scope(f)
+    }
+
+    #[cfg(not(feature = "tracing"))]
+    pub fn in_scope<F, T>(&self, f: F) -> T
+        where F: FnOnce() -> T
+    {
+        f()
+    }
+
+    /// Records how many times the request was retried
+    #[cfg(feature = "tracing")]
+    pub fn record_retries(&self, retries: u32) {
+        self.span.record("retries", u64::from(retries));
+    }
+
+    #[cfg(not(feature = "tracing"))]
+    pub fn record_retries(&self, _retries: u32) {}
+
+    /// Records the outcome of the call and closes the span
+    #[cfg(feature = "tracing")]
+    pub fn finish(self, result: &Result<HttpResponse, ApiError>) {
+        let latency_ms = millis(self.started_at.elapsed());
+        self.span.record("latency_ms", latency_ms);
+
+        match *result {
+            Ok(ref response) => {
+                self.span.record("status", u64::from(response.status));
+                self.span.in_scope(|| debug!(status = u64::from(response.status), latency_ms, "request finished"));
+            }
+            Err(ref e) => {
+                self.span.in_scope(|| warn!(error = ?e, latency_ms, "request failed"));
+            }
+        }
+    }
+
+    #[cfg(not(feature = "tracing"))]
+    pub fn finish(self, _result: &Result<HttpResponse, ApiError>) {}
+}
@@ -5,10 +5,45 @@ extern crate serde;
 extern crate serde_json;
 extern crate serde_urlencoded;
 extern crate chrono;
+extern crate rand;
 extern crate reqwest;
 
+#[cfg(feature = "tracing")]
+#[macro_use]
+extern crate tracing;
+
+pub mod builder;
+pub mod cache;
+pub mod credentials;
+pub mod ids;
+pub mod instrument;
+pub mod middleware;
 pub mod models;
+pub mod oauth;
+pub mod outbox;
+pub mod parser;
+pub mod ratelimit;
+pub mod render;
+pub mod retry;
+pub mod schema;
+pub mod tls;
+pub mod uri;
 pub mod gitter;
 
+pub use builder::*;
+pub use cache::*;
+pub use credentials::*;
 pub use gitter::*;
+pub use ids::*;
+pub use instrument::*;
+pub use middleware::*;
 pub use models::*;
+pub use oauth::*;
+pub use outbox::*;
+pub use parser::*;
+pub use ratelimit::*;
+pub use render::*;
+pub use retry::*;
+pub use schema::*;
+pub use tls::*;
+pub use uri::*;
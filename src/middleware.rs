@@ -0,0 +1,301 @@
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use gitter::ApiError;
+use instrument::redact_headers;
+use retry::millis;
+
+/// HTTP method of a Gitter API request
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HttpMethod {
+    Get,
+    Post,
+    Put,
+    Delete,
+}
+
+impl HttpMethod {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            HttpMethod::Get => "GET",
+            HttpMethod::Post => "POST",
+            HttpMethod::Put => "PUT",
+            HttpMethod::Delete => "DELETE",
+        }
+    }
+}
+
+impl fmt::Display for HttpMethod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Returns the value of the header, header names are case insensitive
+fn find_header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|h| h.0.eq_ignore_ascii_case(name))
+        .map(|h| h.1.as_str())
+}
+
+/// Outgoing Gitter API request. `Debug` hides the token
+#[derive(Clone, PartialEq, Eq)]
+pub struct HttpRequest {
+    pub method: HttpMethod,
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+
+    /// JSON body
+    pub body: Option<String>,
+}
+
+impl HttpRequest {
+    /// Returns the value of the header
+    pub fn header(&self, name: &str) -> Option<&str> {
+        find_header(&self.headers, name)
+    }
+
+    /// Sets the header, replacing the previous value
+    pub fn set_header<N, V>(&mut self, name: N, value: V)
+        where N: Into<String>,
+              V: Into<String>
+    {
+        let name = name.into();
+        self.headers.retain(|h| !h.0.eq_ignore_ascii_case(&name));
+        self.headers.push((name, value.into()));
+    }
+}
+
+impl fmt::Debug for HttpRequest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("HttpRequest")
+            .field("method", &self.method)
+            .field("url", &self.url)
+            .field("headers", &redact_headers(&self.headers))
+            .field("body", &self.body)
+            .finish()
+    }
+}
+
+/// Gitter API response, read completely. `Debug` hides cookies
+#[derive(Clone, PartialEq, Eq)]
+pub struct HttpResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl HttpResponse {
+    /// Creates response without headers
+    pub fn new<S>(status: u16, body: S) -> HttpResponse
+        where S: Into<String>
+    {
+        HttpResponse {
+            status,
+            headers: Vec::new(),
+            body: body.into(),
+        }
+    }
+
+    /// Returns the value of the header
+    pub fn header(&self, name: &str) -> Option<&str> {
+        find_header(&self.headers, name)
+    }
+
+    /// Determines whether the status is 2xx
+    pub fn is_success(&self) -> bool {
+        self.status >= 200 && self.status < 300
+    }
+}
+
+impl fmt::Debug for HttpResponse {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("HttpResponse")
+            .field("status", &self.status)
+            .field("headers", &redact_headers(&self.headers))
+            .field("body", &self.body)
+            .finish()
+    }
+}
+
+/// The rest of the middleware chain, ending with the actual HTTP call
+pub struct Next<'a> {
+    middlewares: &'a [Arc<dyn Middleware>],
+    transport: &'a dyn Fn(HttpRequest) -> Result<HttpResponse, ApiError>,
+}
+
+impl<'a> Next<'a> {
+    pub(crate) fn new(
+        middlewares: &'a [Arc<dyn Middleware>],
+        transport: &'a dyn Fn(HttpRequest) -> Result<HttpResponse, ApiError>,
+    ) -> Next<'a> {
+        Next {
+            middlewares,
+            transport,
+        }
+    }
+
+    /// Passes the request to the next middleware, or sends it
+    pub fn run(self, request: HttpRequest) -> Result<HttpResponse, ApiError> {
+        match self.middlewares.split_first() {
+            Some((middleware, rest)) => middleware.handle(request, Next::new(rest, self.transport)),
+            None => (self.transport)(request),
+        }
+    }
+}
+
+/// Code which runs around every Gitter API call.
+///
+/// A middleware may change the request before passing it to `next`,
+/// change the response `next` returns, or return a response
+/// without calling `next` at all:
+///
+/// ```rust,ignore
+/// struct ReadOnly;
+///
+/// impl Middleware for ReadOnly {
+///     fn handle(&self, request: HttpRequest, next: Next) -> Result<HttpResponse, ApiError> {
+///         match request.method {
+///             HttpMethod::Get => next.run(request),
+///             _ => Ok(HttpResponse::new(403, "read only")),
+///         }
+///     }
+/// }
+/// ```
+pub trait Middleware: Send + Sync {
+    fn handle(&self, request: HttpRequest, next: Next) -> Result<HttpResponse, ApiError>;
+}
+
+/// Writes every call with its status and duration. By default the lines are
+/// `tracing` events with the `tracing` feature, and are dropped without it
+pub struct LoggingMiddleware {
+    write: Box<dyn Fn(&str) + Send + Sync>,
+}
+
+impl Default for LoggingMiddleware {
+    fn default() -> LoggingMiddleware {
+        LoggingMiddleware::new()
+    }
+}
+
+impl LoggingMiddleware {
+    pub fn new() -> LoggingMiddleware {
+        LoggingMiddleware::with_writer(write_event)
+    }
+
+    /// Passes log lines to `write` instead
+    pub fn with_writer<F>(write: F) -> LoggingMiddleware
+        where F: Fn(&str) + Send + Sync + 'static
+    {
+        LoggingMiddleware {
+            write: Box::new(write),
+        }
+    }
+}
+
+impl Middleware for LoggingMiddleware {
+    fn handle(&self, request: HttpRequest, next: Next) -> Result<HttpResponse, ApiError> {
+        let started_at = Instant::now();
+        let call = format!("{} {}", request.method, request.url);
+        let result = next.run(request);
+        let elapsed = millis(started_at.elapsed());
+
+        match result {
+            Ok(ref response) => (self.write)(&format!("{} -> {} ({}ms)", call, response.status, elapsed)),
+            Err(ref e) => (self.write)(&format!("{} -> {:?} ({}ms)", call, e, elapsed)),
+        }
+
+        result
+    }
+}
+
+#[cfg(feature = "tracing")]
+fn write_event(line: &str) {
+    info!(target: "gitter", "{}", line);
+}
+
+#[cfg(not(feature = "tracing"))]
+fn write_event(_line: &str) {}
+
+/// Duration of one call, measured by `TimingMiddleware`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Timing {
+    pub method: HttpMethod,
+    pub url: String,
+
+    /// `None` if the call failed without a response
+    pub status: Option<u16>,
+    pub elapsed: Duration,
+}
+
+/// Measures the duration of every call
+pub struct TimingMiddleware {
+    record: Box<dyn Fn(Timing) + Send + Sync>,
+}
+
+impl TimingMiddleware {
+    /// Passes the duration of every call to `record`
+    pub fn new<F>(record: F) -> TimingMiddleware
+        where F: Fn(Timing) + Send + Sync + 'static
+    {
+        TimingMiddleware {
+            record: Box::new(record),
+        }
+    }
+
+    /// Collects timings into `timings`
+    pub fn collect(timings: Arc<Mutex<Vec<Timing>>>) -> TimingMiddleware {
+        TimingMiddleware::new(move |timing| timings.lock().unwrap().push(timing))
+    }
+}
+
+impl Middleware for TimingMiddleware {
+    fn handle(&self, request: HttpRequest, next: Next) -> Result<HttpResponse, ApiError> {
+        let started_at = Instant::now();
+        let method = request.method;
+        let url = request.url.clone();
+        let result = next.run(request);
+
+        (self.record)(Timing {
+            method,
+            url,
+            status: result.as_ref().ok().map(|r| r.status),
+            elapsed: started_at.elapsed(),
+        });
+
+        result
+    }
+}
+
+/// Adds headers to every request
+#[derive(Debug, Clone, Default)]
+pub struct HeaderMiddleware {
+    headers: Vec<(String, String)>,
+}
+
+impl HeaderMiddleware {
+    pub fn new() -> HeaderMiddleware {
+        HeaderMiddleware::default()
+    }
+
+    /// Adds a header, replacing the value the request already has
+    pub fn header<N, V>(mut self, name: N, value: V) -> HeaderMiddleware
+        where N: Into<String>,
+              V: Into<String>
+    {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+}
+
+impl Middleware for HeaderMiddleware {
+    fn handle(&self, mut request: HttpRequest, next: Next) -> Result<HttpResponse, ApiError> {
+        for (name, value) in &self.headers {
+            request.set_header(name.as_str(), value.as_str());
+        }
+
+        next.run(request)
+    }
+}
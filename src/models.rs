@@ -1,14 +1,120 @@
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::{Map, Value};
+
+use ids::*;
+use uri::*;
+
+/// Implements string conversions and serde support for enums
+/// which keep unknown values in the `Unknown(String)` variant,
+/// so new values on Gitter side do not break deserialization
+macro_rules! string_enum {
+    ($name:ident { $($variant:ident => $value:expr),* $(,)* }) => {
+        impl $name {
+            /// Returns the value as it is sent by Gitter
+            pub fn as_str(&self) -> &str {
+                match *self {
+                    $($name::$variant => $value,)*
+                    $name::Unknown(ref value) => value,
+                }
+            }
+        }
+
+        impl<'a> From<&'a str> for $name {
+            fn from(value: &'a str) -> $name {
+                match value {
+                    $($value => $name::$variant,)*
+                    _ => $name::Unknown(value.to_string()),
+                }
+            }
+        }
+
+        impl Serialize for $name {
+            fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+                where S: Serializer
+            {
+                serializer.serialize_str(self.as_str())
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $name {
+            fn deserialize<D>(deserializer: D) -> Result<$name, D::Error>
+                where D: Deserializer<'de>
+            {
+                String::deserialize(deserializer).map(|value| $name::from(value.as_str()))
+            }
+        }
+    };
+}
+
+/// Models which keep explicit `null`s of their optional fields
+pub(crate) trait KeepNulls {
+    /// Keys of the fields whose `null` is recorded in `extra`
+    const NULLABLE_FIELDS: &'static [&'static str] = &[];
+}
+
+/// Implements serde support for models which tell an explicit `null`
+/// from an absent field. A `null` deserializes to `None` and is recorded
+/// as `null` in `extra`, so the model serializes back to the same fields.
+/// The record is left out once the field has a value.
+///
+/// The model derives its serde support with `#[serde(remote = "Self")]`,
+/// which this wraps
+macro_rules! keep_nulls {
+    ($name:ident { $($field:ident => $key:expr),* $(,)* }) => {
+        impl KeepNulls for $name {
+            const NULLABLE_FIELDS: &'static [&'static str] = &[$($key),*];
+        }
+
+        impl Serialize for $name {
+            fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+                where S: Serializer
+            {
+                let mut stale = Vec::new();
+                $(if self.$field.is_some() && self.extra.get($key) == Some(&Value::Null) {
+                    stale.push($key);
+                })*
+
+                if stale.is_empty() {
+                    return $name::serialize(self, serializer);
+                }
+
+                let mut model = self.clone();
+                for key in stale {
+                    model.extra.remove(key);
+                }
+                $name::serialize(&model, serializer)
+            }
+        }
 
-#[derive(Deserialize, Debug)]
-#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+        impl<'de> Deserialize<'de> for $name {
+            fn deserialize<D>(deserializer: D) -> Result<$name, D::Error>
+                where D: Deserializer<'de>
+            {
+                let fields = Map::<String, Value>::deserialize(deserializer)?;
+                let nulls = <$name as KeepNulls>::NULLABLE_FIELDS
+                    .iter()
+                    .filter(|key| fields.get(**key) == Some(&Value::Null))
+                    .collect::<Vec<_>>();
+
+                let mut model = $name::deserialize(Value::Object(fields)).map_err(de::Error::custom)?;
+                for key in nulls {
+                    model.extra.insert(key.to_string(), Value::Null);
+                }
+
+                Ok(model)
+            }
+        }
+    };
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum GithubType {
     /// A room that represents a GitHub Organisation.
     Org,
     /// A room that represents a GitHub Repository.
     Repo,
     /// A one-to-one chat.
-    #[serde(rename = "ONETOONE")]
     OneToOne, 
     /// A Gitter channel nested under a GitHub Organisation.
     OrgChannel, 
@@ -16,46 +122,62 @@ pub enum GithubType {
     RepoChannel, 
     /// A Gitter channel nested under a GitHub User.
     UserChannel, 
+    /// A room type this version of the library does not know about
+    Unknown(String),
 }
 
+string_enum!(GithubType {
+    Org => "ORG",
+    Repo => "REPO",
+    OneToOne => "ONETOONE",
+    OrgChannel => "ORG_CHANNEL",
+    RepoChannel => "REPO_CHANNEL",
+    UserChannel => "USER_CHANNEL",
+});
+
 /// A Room in Gitter can represent a GitHub Organization, a GitHub Repository,
 /// a Gitter Channel or a One-to-one conversation.
 /// In the case of the Organizations and Repositories,
 /// the access control policies are inherited from GitHub.
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(remote = "Self")]
 pub struct Room {
     /// Room ID
-    pub id: String,
+    pub id: RoomId,
 
     /// Room name
     pub name: String,
 
     /// Room topic. (default: GitHub repo description)
+    #[serde(default)]
     pub topic: String,
 
     /// Room URI on Gitter
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub uri: Option<String>,
 
     /// Indicates if the room is a one-to-one chat
-    #[serde(rename = "oneToOne")]
+    #[serde(rename = "oneToOne", default)]
     pub one_to_one: bool,
 
     /// Count of users in the room
-    #[serde(rename = "userCount")]
+    #[serde(rename = "userCount", default)]
     pub user_count: i32,
 
     /// Number of unread messages for the current user
-    #[serde(rename = "unreadItems")]
+    #[serde(rename = "unreadItems", default)]
     pub unread_items: i32,
 
     /// Number of unread mentions for the current user
+    #[serde(default)]
     pub mentions: i32,
 
     /// Last time the current user accessed the room in ISO format
-    #[serde(rename = "lastAccessTime")]
+    #[serde(rename = "lastAccessTime", skip_serializing_if = "Option::is_none", serialize_with = "serialize_opt_date")]
     pub last_access_time: Option<DateTime<Utc>>,
 
     /// Indicates if the current user has disabled notifications
+    #[serde(default)]
     pub lurk: bool,
 
     /// Path to the room on gitter
@@ -72,15 +194,84 @@ pub struct Room {
     pub github_type: GithubType,
 
     /// Tags that define the room
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub tags: Option<Vec<String>>,
 
     /// Determines, whether current user is room member or not
-    #[serde(rename = "roomMember")]
+    #[serde(rename = "roomMember", default)]
     pub room_member: bool,
 
+    /// ID of the group the room belongs to
+    #[serde(rename = "groupId", skip_serializing_if = "Option::is_none")]
+    pub group_id: Option<GroupId>,
+
+    /// Permissions of the current user in the room
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub permissions: Option<Permissions>,
+
     /// Room version
-    #[serde(rename = "v")]
+    #[serde(rename = "v", skip_serializing_if = "Option::is_none")]
     pub version: Option<i32>,
+
+    /// Fields returned by Gitter which are not modeled by this library
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+keep_nulls!(Room {
+    uri => "uri",
+    last_access_time => "lastAccessTime",
+    tags => "tags",
+    group_id => "groupId",
+    permissions => "permissions",
+    version => "v"
+});
+
+/// Permissions of the current user in a room, as returned by Gitter
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+pub struct Permissions {
+    /// Whether the current user is an admin of the room
+    #[serde(default)]
+    pub admin: bool,
+
+    /// Fields returned by Gitter which are not modeled by this library
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl KeepNulls for Permissions {}
+
+/// Role of the current user in a room
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RoomRole {
+    /// User can administer the room (update settings, remove users)
+    Admin,
+    /// User is a regular member of the room
+    Member,
+    /// User can see the room, but has not joined it
+    Guest,
+}
+
+/// Permissions of the current user in a room
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RoomPermissions {
+    /// Role of the current user, `None` if the admin rights cannot be determined
+    pub role: Option<RoomRole>,
+
+    /// Determines whether the current user has joined the room
+    pub member: bool,
+
+    /// Security descriptor of the room's group, if the room belongs to one.
+    /// Describes the backing object admin rights are inherited from.
+    pub backed_by: Option<BackedBy>,
+}
+
+impl RoomPermissions {
+    /// Determines whether the current user is an admin of the room,
+    /// `None` if it cannot be determined
+    pub fn is_admin(&self) -> Option<bool> {
+        self.role.map(|role| role == RoomRole::Admin)
+    }
 }
 
 /// Join room request model
@@ -88,34 +279,61 @@ pub struct Room {
 pub struct JoinRoom {
     /// Room ID to join
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub id: Option<String>,
+    pub id: Option<RoomId>,
+    /// Room URI to join
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub uri: Option<String>,
+    pub uri: Option<RoomUri>,
 }
 
 impl JoinRoom {
     /// Create join room request from room ID
-    pub fn from_id<S>(id: S) -> JoinRoom 
-        where S: AsRef<str>
-    {
+    pub fn from_id(id: &RoomId) -> JoinRoom {
         JoinRoom {
-            id: Some(id.as_ref().to_string()),
+            id: Some(id.clone()),
             uri: None,
         }
     }
 
     /// Create join room request from URI
-    pub fn from_uri<S>(uri: S) -> JoinRoom 
-        where S: AsRef<str> 
-    {
+    pub fn from_uri(uri: &RoomUri) -> JoinRoom {
         JoinRoom {
             id: None,
-            uri: Some(uri.as_ref().to_string()),
+            uri: Some(uri.clone()),
         }
     }
 }
 
-#[derive(Serialize, Debug)]
+/// Room security (visibility) setting
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Security {
+    /// Anyone can find and join the room
+    Public,
+    /// Only invited users can join the room
+    Private,
+    /// Permissions are inherited from the backing GitHub object
+    Inherited,
+    /// A security setting this version of the library does not know about
+    Unknown(String),
+}
+
+string_enum!(Security {
+    Public => "PUBLIC",
+    Private => "PRIVATE",
+    Inherited => "INHERITED",
+});
+
+/// Update room request model.
+///
+/// Fields that are not set are not serialized, so only the given
+/// settings are changed on the server:
+///
+/// ```rust,ignore
+/// let update = UpdateRoom::new()
+///     .topic("Rust API for Gitter")
+///     .tags(vec!["rust", "api"])
+///     .noindex(false);
+/// ```
+#[derive(Serialize, Debug, Default, Clone)]
 pub struct UpdateRoom {
     /// Room topic
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -126,47 +344,111 @@ pub struct UpdateRoom {
     pub noindex: Option<bool>,
 
     /// Tags that define the room
+    #[serde(skip_serializing_if = "Option::is_none", serialize_with = "serialize_tags")]
+    pub tags: Option<Vec<String>>,
+
+    /// Room security (visibility) setting
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub tags: Option<String>
+    pub security: Option<Security>,
 }
 
 impl UpdateRoom {
+    /// Create empty update room request
+    pub fn new() -> UpdateRoom {
+        UpdateRoom::default()
+    }
+
     /// Create update room request only with topic parameter
     pub fn from_topic<S>(topic: S) -> UpdateRoom 
         where S: AsRef<str>
     {
-        UpdateRoom {
-            topic: Some(topic.as_ref().to_string()),
-            noindex: None,
-            tags: None
-        }
+        UpdateRoom::new().topic(topic)
     }
 
     /// Create update room request only with noindex parameter
     pub fn from_noindex(noindex: bool) -> UpdateRoom {
-        UpdateRoom {
-            topic: None,
-            noindex: Some(noindex),
-            tags: None
-        }
+        UpdateRoom::new().noindex(noindex)
     }
 
-    /// Create update room request only with tags parameter
+    /// Create update room request only with tags parameter.
+    /// Tags are given as a comma separated string
     pub fn from_tags<S>(tags: S) -> UpdateRoom 
         where S: AsRef<str>
     {
-        UpdateRoom {
-            topic: None,
-            noindex: None,
-            tags: Some(tags.as_ref().to_string()),
-        }
+        UpdateRoom::new().tags(
+            tags.as_ref()
+                .split(',')
+                .map(|t| t.trim())
+                .filter(|t| !t.is_empty()),
+        )
+    }
+
+    /// Set room topic
+    pub fn topic<S>(mut self, topic: S) -> UpdateRoom
+        where S: AsRef<str>
+    {
+        self.topic = Some(topic.as_ref().to_string());
+        self
+    }
+
+    /// Set whether the room is indexed by search engines
+    pub fn noindex(mut self, noindex: bool) -> UpdateRoom {
+        self.noindex = Some(noindex);
+        self
+    }
+
+    /// Set room tags
+    pub fn tags<I, S>(mut self, tags: I) -> UpdateRoom
+        where I: IntoIterator<Item = S>,
+              S: AsRef<str>
+    {
+        self.tags = Some(tags.into_iter().map(|t| t.as_ref().to_string()).collect());
+        self
+    }
+
+    /// Set room security
+    pub fn security(mut self, security: Security) -> UpdateRoom {
+        self.security = Some(security);
+        self
+    }
+
+    /// Make the room public or private
+    pub fn public(self, public: bool) -> UpdateRoom {
+        self.security(if public { Security::Public } else { Security::Private })
+    }
+}
+
+/// Gitter sends dates in JavaScript ISO format, always with milliseconds
+fn serialize_date<S>(date: &DateTime<Utc>, serializer: S) -> Result<S::Ok, S::Error>
+    where S: Serializer
+{
+    serializer.serialize_str(&date.to_rfc3339_opts(SecondsFormat::Millis, true))
+}
+
+fn serialize_opt_date<S>(date: &Option<DateTime<Utc>>, serializer: S) -> Result<S::Ok, S::Error>
+    where S: Serializer
+{
+    match *date {
+        Some(ref date) => serialize_date(date, serializer),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// Gitter expects room tags as a single comma separated string
+fn serialize_tags<S>(tags: &Option<Vec<String>>, serializer: S) -> Result<S::Ok, S::Error>
+    where S: Serializer
+{
+    match *tags {
+        Some(ref tags) => serializer.serialize_str(&tags.join(",")),
+        None => serializer.serialize_none(),
     }
 }
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(remote = "Self")]
 pub struct User {
     /// Gitter User ID
-    pub id: String,
+    pub id: UserId,
 
     /// Gitter/GitHub username
     pub username: String,
@@ -179,38 +461,52 @@ pub struct User {
     pub url: String,
 
     /// User avatar URI
-    #[serde(rename = "avatarUrl")]
+    #[serde(rename = "avatarUrl", skip_serializing_if = "Option::is_none")]
     pub avatar_url: Option<String>,
 
     /// User avatar URI (small)
-    #[serde(rename = "avatarUrlSmall")]
+    #[serde(rename = "avatarUrlSmall", skip_serializing_if = "Option::is_none")]
     pub avatar_url_small: Option<String>,
 
     /// User avatar URI (medium)
-    #[serde(rename = "avatarUrlMedium")]
+    #[serde(rename = "avatarUrlMedium", skip_serializing_if = "Option::is_none")]
     pub avatar_url_medium: Option<String>,
 
     /// Version
-    #[serde(rename = "v")]
+    #[serde(rename = "v", skip_serializing_if = "Option::is_none")]
     pub version: Option<i32>,
+
+    /// Fields returned by Gitter which are not modeled by this library
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
 
-#[derive(Deserialize, Debug)]
+keep_nulls!(User {
+    avatar_url => "avatarUrl",
+    avatar_url_small => "avatarUrlSmall",
+    avatar_url_medium => "avatarUrlMedium",
+    version => "v"
+});
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(remote = "Self")]
 pub struct Message {
     /// ID of the message
-    pub id: String,
+    pub id: MessageId,
 
     /// Original message in plain-text/markdown
     pub text: String,
 
     /// HTML formatted message
+    #[serde(default)]
     pub html: String,
 
     /// ISO formatted date of the message
+    #[serde(serialize_with = "serialize_date")]
     pub sent: DateTime<Utc>,
 
     /// ISO formatted date of the message if edited
-    #[serde(rename = "editedAt")]
+    #[serde(rename = "editedAt", skip_serializing_if = "Option::is_none", serialize_with = "serialize_opt_date")]
     pub edited_at: Option<DateTime<Utc>>,
 
     /// User that sent the message
@@ -218,76 +514,238 @@ pub struct Message {
     pub from: User,
 
     /// Boolean that indicates if the current user has read the message.
+    #[serde(default)]
     pub unread: bool,
 
     /// Number of users that have read the message
-    #[serde(rename = "readBy")]
+    #[serde(rename = "readBy", default)]
     pub read_by: i32,
 
     /// List of URLs present in the message
+    #[serde(default)]
     pub urls: Vec<Url>,
 
     /// List of @Mentions in the message
+    #[serde(default)]
     pub mentions: Vec<Mention>,
 
     /// List of #Issues referenced in the message
+    #[serde(default)]
     pub issues: Vec<Issue>,
 
+    /// Additional data attached to the message by Gitter
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub meta: Option<Vec<MessageMeta>>,
+
+    /// Indicates if the message is a status (`/me`) message
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<bool>,
+
+    /// ID of the thread parent message if the message is a thread reply
+    #[serde(rename = "parentId", skip_serializing_if = "Option::is_none")]
+    pub parent_id: Option<MessageId>,
+
+    /// Number of replies if the message is a thread parent
+    #[serde(rename = "threadMessageCount", skip_serializing_if = "Option::is_none")]
+    pub thread_message_count: Option<i32>,
+
+    /// Original sender if the message was bridged from another network
+    #[serde(rename = "virtualUser", skip_serializing_if = "Option::is_none")]
+    pub virtual_user: Option<VirtualUser>,
+
     /// Version
-    #[serde(rename = "v")]
+    #[serde(rename = "v", skip_serializing_if = "Option::is_none")]
     pub version: Option<i32>,
+
+    /// Fields returned by Gitter which are not modeled by this library
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+keep_nulls!(Message {
+    edited_at => "editedAt",
+    meta => "meta",
+    status => "status",
+    parent_id => "parentId",
+    thread_message_count => "threadMessageCount",
+    virtual_user => "virtualUser",
+    version => "v"
+});
+
+impl Message {
+    /// Determines whether the message is a reply in a thread
+    pub fn is_thread_reply(&self) -> bool {
+        self.parent_id.is_some()
+    }
+
+    /// Returns the name to display as the sender,
+    /// taking bridged (virtual) users into account
+    pub fn sender_name(&self) -> &str {
+        match self.virtual_user {
+            Some(ref virtual_user) => &virtual_user.display_name,
+            None => &self.from.display_name,
+        }
+    }
 }
 
+/// Entry of the additional data attached to a message
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum MessageMeta {
+    /// Object entry, which names its kind in `type`
+    Entry(MetaEntry),
+
+    /// Entry of another shape, kept as Gitter sent it
+    Other(Value),
+}
+
+/// Additional data of a message, e.g. an embed
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct MetaEntry {
+    /// Kind of the data
+    #[serde(rename = "type")]
+    pub meta_type: String,
+
+    /// Fields returned by Gitter which are not modeled by this library
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl KeepNulls for MetaEntry {}
+
+/// User of another network (e.g. Matrix) whose messages are bridged to Gitter
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(remote = "Self")]
+pub struct VirtualUser {
+    /// Network the user comes from
+    #[serde(rename = "type")]
+    pub user_type: String,
+
+    /// User ID in that network
+    #[serde(rename = "externalId")]
+    pub external_id: String,
+
+    /// User display name
+    #[serde(rename = "displayName")]
+    pub display_name: String,
+
+    /// User avatar URI
+    #[serde(rename = "avatarUrl", skip_serializing_if = "Option::is_none")]
+    pub avatar_url: Option<String>,
+
+    /// Fields returned by Gitter which are not modeled by this library
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+keep_nulls!(VirtualUser {
+    avatar_url => "avatarUrl"
+});
+
 /// Send message request model
 #[derive(Serialize, Debug)]
 pub struct OutMessage<'a> {
     /// Original message in plain-text/markdown
     pub text: &'a str,
+
+    /// Message to reply to in its thread
+    #[serde(rename = "parentId", skip_serializing_if = "Option::is_none")]
+    pub parent_id: Option<&'a MessageId>,
 }
 
 /// Mention holds data about mentioned user in the message
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(remote = "Self")]
 pub struct Mention {
     /// User's username
     #[serde(rename = "screenName")]
     pub screen_name: String,
 
-    /// Gitter User ID
-    #[serde(rename = "userId")]
-    pub user_id: String,
+    /// Gitter User ID. Absent for group mentions
+    /// and for users which are not on Gitter
+    #[serde(rename = "userId", skip_serializing_if = "Option::is_none")]
+    pub user_id: Option<UserId>,
 
     /// Announcement 
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub announcement: Option<bool>,
 
     /// To all group
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub group: Option<bool>,
+
+    /// IDs of the mentioned group members
+    #[serde(rename = "userIds", skip_serializing_if = "Option::is_none")]
+    pub user_ids: Option<Vec<UserId>>,
+
+    /// Fields returned by Gitter which are not modeled by this library
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
 
+keep_nulls!(Mention {
+    user_id => "userId",
+    announcement => "announcement",
+    group => "group",
+    user_ids => "userIds"
+});
+
 /// Issue references issue in the message
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(remote = "Self")]
 pub struct Issue {
     /// Issue number
     pub number: String,
+
+    /// Repository of the issue, if it is not the room's repository
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub repo: Option<String>,
+
+    /// Indicates if the issue is a pull request
+    #[serde(rename = "isPullRequest", skip_serializing_if = "Option::is_none")]
+    pub is_pull_request: Option<bool>,
+
+    /// Fields returned by Gitter which are not modeled by this library
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
 
+keep_nulls!(Issue {
+    repo => "repo",
+    is_pull_request => "isPullRequest"
+});
+
 /// URL presented in the message
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(remote = "Self")]
 pub struct Url {
     /// URL
     pub url: String,
+
+    /// Type of the linked resource (e.g. `issue`, `image`)
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    pub url_type: Option<String>,
+
+    /// Fields returned by Gitter which are not modeled by this library
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
 
+keep_nulls!(Url {
+    url_type => "type"
+});
+
 /// Search rooms result
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub struct SearchResult {
     #[serde(rename = "results")]
     pub rooms: Vec<Room>,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub struct Group {
     /// Group ID
-    pub id: String,
+    pub id: GroupId,
 
     /// Group name.
     pub name: String,
@@ -302,41 +760,66 @@ pub struct Group {
     /// Base avatar URL (add s parameter to size)
     #[serde(rename = "avatarUrl")]
     pub avatar_url: String,
+
+    /// Fields returned by Gitter which are not modeled by this library
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
 
+impl KeepNulls for Group {}
+
 /// Security descriptor. Describes the backing object we get permissions from.
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(remote = "Self")]
 pub struct BackedBy {
-    #[serde(rename = "type")]
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
     pub group_type: Option<GroupType>,
 
     /// Represents how we find the backing object given the type
-    #[serde(rename = "linkPath")]
+    #[serde(rename = "linkPath", skip_serializing_if = "Option::is_none")]
     pub link_path: Option<String>,
+
+    /// Fields returned by Gitter which are not modeled by this library
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
 
-#[derive(Deserialize, Debug)]
-#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+keep_nulls!(BackedBy {
+    group_type => "type",
+    link_path => "linkPath"
+});
+
+/// Type of the object a group gets its permissions from
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum GroupType {
     OneToOne,
     GhRepo,
     GhOrg,
     GhUser,
+    /// A group type this version of the library does not know about
+    Unknown(String),
 }
 
+string_enum!(GroupType {
+    OneToOne => "ONE_TO_ONE",
+    GhRepo => "GH_REPO",
+    GhOrg => "GH_ORG",
+    GhUser => "GH_USER",
+});
+
 /// Unread messages and mentions
 #[derive(Serialize, Deserialize, Debug)]
 pub struct UnreadItems {
     /// Unread messages
-    pub chat: Option<Vec<String>>,
+    pub chat: Option<Vec<MessageId>>,
 
     /// Mentioned messaged
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub mention: Option<Vec<String>>,
+    pub mention: Option<Vec<MessageId>>,
 }
 
 impl UnreadItems {
-    pub fn from_msg_ids(msg_ids: &[String]) -> UnreadItems {
+    pub fn from_msg_ids(msg_ids: &[MessageId]) -> UnreadItems {
         UnreadItems {
             chat: Some(msg_ids.to_owned()),
             mention: None,
@@ -345,7 +828,8 @@ impl UnreadItems {
 }
 
 /// Github organization
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(remote = "Self")]
 pub struct Organization {
     /// Organization ID
     pub id: u64,
@@ -354,15 +838,26 @@ pub struct Organization {
     pub name: String,
 
     /// Organization avatar url
-    #[serde(rename = "avatarUrl")]
+    #[serde(rename = "avatarUrl", skip_serializing_if = "Option::is_none")]
     pub avatar_url: Option<String>,
 
     /// Organization respective room
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub room: Option<Room>,
+
+    /// Fields returned by Gitter which are not modeled by this library
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
 
+keep_nulls!(Organization {
+    avatar_url => "avatarUrl",
+    room => "room"
+});
+
 /// Github repository info
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(remote = "Self")]
 pub struct Repository {
     /// Repository ID
     pub id: u64,
@@ -374,42 +869,57 @@ pub struct Repository {
     pub uri: String,
 
     /// Determines whether repository is private or not
+    #[serde(default)]
     pub private: bool,
 
     /// Repository respective room
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub room: Option<Room>,
+
+    /// Fields returned by Gitter which are not modeled by this library
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
 
+keep_nulls!(Repository {
+    room => "room"
+});
+
 /// Gitter channel
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(remote = "Self")]
 pub struct Channel {
     /// Channel ID
-    pub id: String,
+    pub id: RoomId,
 
     /// Channel name
     pub name: String,
 
     /// Channel topic
+    #[serde(default)]
     pub topic: String,
 
     /// Channel URI
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub uri: Option<String>,
 
     /// Is channel one-to-one
-    #[serde(rename = "oneToOne")]
+    #[serde(rename = "oneToOne", default)]
     pub one_to_one: bool,
 
-    #[serde(rename = "unreadItems")]
     /// Unread items in channel
+    #[serde(rename = "unreadItems", default)]
     pub unread_items: i32,
 
     /// Mentions in channel
+    #[serde(default)]
     pub mentions: i32,
 
     /// Last access date and time
-    #[serde(rename = "lastAccessTime")]
+    #[serde(rename = "lastAccessTime", skip_serializing_if = "Option::is_none", serialize_with = "serialize_opt_date")]
     pub last_access_time: Option<DateTime<Utc>>,
 
+    #[serde(default)]
     pub lurk: bool,
 
     pub url: String,
@@ -417,6 +927,15 @@ pub struct Channel {
     #[serde(rename = "githubType")]
     pub github_type: GithubType,
 
-    pub security: String,
+    pub security: Security,
+
+    /// Fields returned by Gitter which are not modeled by this library
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
 
+keep_nulls!(Channel {
+    uri => "uri",
+    last_access_time => "lastAccessTime"
+});
+
@@ -0,0 +1,482 @@
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use rand::RngCore;
+use rand::rngs::OsRng;
+use reqwest::Client;
+use serde_urlencoded;
+
+use credentials::*;
+use gitter::*;
+use tls::*;
+
+/// Gitter OAuth authorization endpoint
+pub const AUTHORIZE_URL: &str = "https://gitter.im/login/oauth/authorize";
+
+/// Gitter OAuth token endpoint
+pub const TOKEN_URL: &str = "https://gitter.im/login/oauth/token";
+
+/// How often the redirect listener checks for the browser
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Longest time a connection to the redirect listener may take to send its request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Longest request head the redirect listener reads
+const MAX_HEAD_LEN: usize = 16 * 1024;
+
+/// Error returned by the OAuth flow
+#[derive(Debug)]
+pub enum OAuthError {
+    /// The redirect listener failed
+    Io(String),
+    /// The browser did not come back before the timeout
+    Timeout,
+    /// The `state` of the redirect differs from the one sent, the request was forged.
+    /// The listener refuses such requests and keeps waiting for the browser
+    StateMismatch,
+    /// The user or Gitter denied the authorization
+    Denied(String),
+    /// The redirect has no authorization code
+    MissingCode,
+    /// The token endpoint did not return a token
+    TokenExchange(String),
+    /// The API client could not be created
+    Api(ApiError),
+}
+
+impl fmt::Display for OAuthError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            OAuthError::Io(ref e) => write!(f, "redirect listener failed: {}", e),
+            OAuthError::Timeout => f.write_str("timed out waiting for the authorization redirect"),
+            OAuthError::StateMismatch => f.write_str("authorization redirect has a wrong state"),
+            OAuthError::Denied(ref e) => write!(f, "authorization denied: {}", e),
+            OAuthError::MissingCode => f.write_str("authorization redirect has no code"),
+            OAuthError::TokenExchange(ref e) => write!(f, "cannot exchange code for token: {}", e),
+            OAuthError::Api(ref e) => write!(f, "cannot create API client: {:?}", e),
+        }
+    }
+}
+
+impl Error for OAuthError {
+    fn description(&self) -> &str {
+        "Gitter OAuth flow failed"
+    }
+}
+
+impl From<io::Error> for OAuthError {
+    fn from(e: io::Error) -> OAuthError {
+        OAuthError::Io(e.to_string())
+    }
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+}
+
+/// Gets an access token through the Gitter OAuth web flow.
+///
+/// Opens a temporary listener on the loopback address for the redirect,
+/// so the redirect URL of the OAuth app must be `http://127.0.0.1:<port><path>`:
+///
+/// ```rust,ignore
+/// let oauth = OAuth::new(client_id, client_secret).redirect_port(8765);
+///
+/// let authorization = oauth.start()?;
+/// println!("Open {} in your browser", authorization.url());
+/// let api = authorization.wait()?;
+/// ```
+///
+/// The endpoints can be changed to run the flow against a stand-in server
+#[derive(Clone)]
+pub struct OAuth {
+    client_id: String,
+    client_secret: Arc<SecretToken>,
+    authorize_url: String,
+    token_url: String,
+    api_base_url: Option<String>,
+    redirect_port: u16,
+    redirect_path: String,
+    timeout: Duration,
+    client: Option<Client>,
+    tls: TlsConfig,
+}
+
+impl fmt::Debug for OAuth {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("OAuth")
+            .field("client_id", &self.client_id)
+            .field("authorize_url", &self.authorize_url)
+            .field("token_url", &self.token_url)
+            .field("api_base_url", &self.api_base_url)
+            .field("redirect_port", &self.redirect_port)
+            .field("redirect_path", &self.redirect_path)
+            .field("timeout", &self.timeout)
+            .field("client", &self.client.is_some())
+            .field("tls", &self.tls)
+            .finish()
+    }
+}
+
+impl OAuth {
+    /// Creates the flow of the OAuth app, listening for the redirect on
+    /// a random free port, at `/callback`, for 5 minutes
+    pub fn new<I, S>(client_id: I, client_secret: S) -> OAuth
+        where I: Into<String>,
+              S: Into<String>
+    {
+        OAuth {
+            client_id: client_id.into(),
+            client_secret: Arc::new(SecretToken::new(client_secret)),
+            authorize_url: AUTHORIZE_URL.to_string(),
+            token_url: TOKEN_URL.to_string(),
+            api_base_url: None,
+            redirect_port: 0,
+            redirect_path: "/callback".to_string(),
+            timeout: Duration::from_secs(5 * 60),
+            client: None,
+            tls: TlsConfig::new(),
+        }
+    }
+
+    /// Changes the authorization endpoint the user is sent to
+    pub fn authorize_url<S>(mut self, authorize_url: S) -> OAuth
+        where S: Into<String>
+    {
+        self.authorize_url = authorize_url.into();
+        self
+    }
+
+    /// Changes the endpoint which exchanges the code for a token
+    pub fn token_url<S>(mut self, token_url: S) -> OAuth
+        where S: Into<String>
+    {
+        self.token_url = token_url.into();
+        self
+    }
+
+    /// Changes the API base url of the returned client
+    pub fn api_base_url<S>(mut self, api_base_url: S) -> OAuth
+        where S: Into<String>
+    {
+        self.api_base_url = Some(api_base_url.into());
+        self
+    }
+
+    /// Sets the port of the redirect listener, it must match the redirect URL
+    /// of the OAuth app. Zero picks a free port
+    pub fn redirect_port(mut self, redirect_port: u16) -> OAuth {
+        self.redirect_port = redirect_port;
+        self
+    }
+
+    /// Sets the path of the redirect URL
+    pub fn redirect_path<S>(mut self, redirect_path: S) -> OAuth
+        where S: Into<String>
+    {
+        self.redirect_path = redirect_path.into();
+        self
+    }
+
+    /// Sets how long to wait for the browser to come back
+    pub fn timeout(mut self, timeout: Duration) -> OAuth {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Sets custom CA certificates and the client certificate
+    /// of the token exchange and of the returned client
+    pub fn tls(mut self, tls: TlsConfig) -> OAuth {
+        self.tls = tls;
+        self
+    }
+
+    /// Exchanges the code and sends the requests of the returned client
+    /// through an existing HTTP client. The TLS settings are ignored then
+    pub fn client(mut self, client: Client) -> OAuth {
+        self.client = Some(client);
+        self
+    }
+
+    /// Starts the redirect listener and creates the authorization URL with a new state
+    pub fn start(&self) -> Result<Authorization, OAuthError> {
+        // The browser connects to the address of the redirect URL,
+        // `localhost` could resolve to the IPv6 loopback instead
+        let listener = TcpListener::bind(("127.0.0.1", self.redirect_port))?;
+        let redirect_uri = format!("http://127.0.0.1:{}{}", listener.local_addr()?.port(), self.redirect_path);
+        let state = new_state()?;
+
+        // The token exchange and the returned client share one HTTP client
+        let mut oauth = self.clone();
+        oauth.client = Some(self.http_client()?);
+
+        let query = serde_urlencoded::to_string([
+            ("client_id", self.client_id.as_str()),
+            ("redirect_uri", redirect_uri.as_str()),
+            ("response_type", "code"),
+            ("state", state.expose()),
+        ]).map_err(|e| OAuthError::Io(e.to_string()))?;
+
+        Ok(Authorization {
+            oauth,
+            url: format!("{}?{}", self.authorize_url, query),
+            redirect_uri,
+            state,
+            listener,
+        })
+    }
+
+    /// Exchanges the authorization code for an access token
+    pub fn exchange_code<S>(&self, code: S, redirect_uri: &str) -> Result<SecretToken, OAuthError>
+        where S: AsRef<str>
+    {
+        let form = [
+            ("client_id", self.client_id.as_str()),
+            ("client_secret", self.client_secret.expose()),
+            ("code", code.as_ref()),
+            ("redirect_uri", redirect_uri),
+            ("grant_type", "authorization_code"),
+        ];
+
+        let client = self.http_client()?;
+        let mut response = client
+            .post(self.token_url.as_str())
+            .form(&form)
+            .send()
+            .map_err(|e| OAuthError::TokenExchange(e.to_string()))?;
+
+        if !response.status().is_success() {
+            let body = response.text().unwrap_or_default();
+            return Err(OAuthError::TokenExchange(format!("{}: {}", response.status(), body)));
+        }
+
+        let token = response
+            .json::<TokenResponse>()
+            .map_err(|e| OAuthError::TokenExchange(e.to_string()))?;
+
+        Ok(SecretToken::new(token.access_token))
+    }
+
+    fn http_client(&self) -> Result<Client, OAuthError> {
+        match self.client {
+            Some(ref client) => Ok(client.clone()),
+            None => build_client(TIMEOUT, &self.tls).map_err(OAuthError::Api),
+        }
+    }
+}
+
+/// Started OAuth flow, waiting for the user to authorize the app
+#[derive(Debug)]
+pub struct Authorization {
+    oauth: OAuth,
+    url: String,
+    redirect_uri: String,
+    state: SecretToken,
+    listener: TcpListener,
+}
+
+impl Authorization {
+    /// Returns the URL to open in the user's browser
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Returns the URL Gitter redirects the browser to
+    pub fn redirect_uri(&self) -> &str {
+        &self.redirect_uri
+    }
+
+    /// Waits for the redirect, checks its state and exchanges the code for a token
+    pub fn wait_for_token(self) -> Result<SecretToken, OAuthError> {
+        let code = self.wait_for_code()?;
+        self.oauth.exchange_code(code.expose(), &self.redirect_uri)
+    }
+
+    /// Waits for the token and creates the API client
+    pub fn wait(self) -> Result<Gitter, OAuthError> {
+        let api_base_url = self.oauth.api_base_url.clone();
+        let client = self.oauth.http_client()?;
+        let token = self.wait_for_token()?;
+        let api = GitterBuilder::from_credentials(token)
+            .client(client)
+            .build()
+            .map_err(OAuthError::Api)?;
+
+        Ok(match api_base_url {
+            Some(api_base_url) => api.with_api_base_url(api_base_url),
+            None => api,
+        })
+    }
+
+    /// Accepts connections until the browser comes to the redirect path with
+    /// the right state. Connections which fail, stay idle or carry a wrong state
+    /// are answered with 400 and dropped, without stopping the wait
+    fn wait_for_code(&self) -> Result<SecretToken, OAuthError> {
+        let deadline = Instant::now() + self.oauth.timeout;
+        self.listener.set_nonblocking(true)?;
+
+        // Requests are read side by side, so a slow connection cannot hold up the browser
+        let mut pending = Vec::new();
+
+        loop {
+            loop {
+                match self.listener.accept() {
+                    Ok((stream, _)) => {
+                        if let Ok(request) = PendingRequest::new(stream) {
+                            pending.push(request);
+                        }
+                    }
+                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                    Err(ref e) if is_connection_error(e) => {}
+                    Err(e) => return Err(e.into()),
+                }
+            }
+
+            let mut i = 0;
+            while i < pending.len() {
+                match pending[i].poll() {
+                    Ok(None) => i += 1,
+                    Ok(Some(target)) => {
+                        let mut request = pending.swap_remove(i);
+                        if let Some(result) = self.handle_redirect(&mut request.stream, &target) {
+                            return result;
+                        }
+                    }
+                    Err(e) => {
+                        let mut request = pending.swap_remove(i);
+                        let _ = respond(&mut request.stream, "400 Bad Request", &e.to_string());
+                    }
+                }
+            }
+
+            if Instant::now() >= deadline {
+                return Err(OAuthError::Timeout);
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    /// Answers a request of the browser. Returns the code, or the error Gitter
+    /// redirected with. Returns `None` for requests to other paths, like
+    /// the favicon, and for redirects with a wrong state
+    fn handle_redirect(&self, stream: &mut TcpStream, target: &str) -> Option<Result<SecretToken, OAuthError>> {
+        let mut parts = target.splitn(2, '?');
+        let path = parts.next().unwrap_or("");
+        let query = parts.next().unwrap_or("");
+
+        if path != self.oauth.redirect_path {
+            let _ = respond(stream, "404 Not Found", "Not found");
+            return None;
+        }
+
+        let params: Vec<(String, String)> = serde_urlencoded::from_str(query).unwrap_or_default();
+        let param = |name: &str| params.iter().find(|p| p.0 == name).map(|p| p.1.as_str());
+
+        if param("state").filter(|state| constant_time_eq(state, self.state.expose())).is_none() {
+            let _ = respond(stream, "400 Bad Request", &OAuthError::StateMismatch.to_string());
+            return None;
+        }
+
+        let result = match param("error") {
+            Some(error) => Err(OAuthError::Denied(param("error_description").unwrap_or(error).to_string())),
+            None => param("code").map(SecretToken::new).ok_or(OAuthError::MissingCode),
+        };
+
+        // The code is valid even if the browser does not get the page
+        let _ = match result {
+            Ok(_) => respond(stream, "200 OK", "Authorized, you can close this page."),
+            Err(ref e) => respond(stream, "400 Bad Request", &e.to_string()),
+        };
+
+        Some(result)
+    }
+}
+
+/// Connection to the redirect listener whose request is still being read
+struct PendingRequest {
+    stream: TcpStream,
+    head: Vec<u8>,
+    accepted_at: Instant,
+}
+
+impl PendingRequest {
+    fn new(stream: TcpStream) -> Result<PendingRequest, OAuthError> {
+        stream.set_nonblocking(true)?;
+
+        Ok(PendingRequest {
+            stream,
+            head: Vec::new(),
+            accepted_at: Instant::now(),
+        })
+    }
+
+    /// Reads what has arrived and returns the request target, e.g.
+    /// `/callback?code=...`, once the request head is complete
+    fn poll(&mut self) -> io::Result<Option<String>> {
+        let mut buf = [0; 1024];
+
+        loop {
+            if let Some(end) = self.head.windows(4).position(|w| w == b"\r\n\r\n") {
+                let head = String::from_utf8_lossy(&self.head[..end]);
+                return Ok(Some(head.split_whitespace().nth(1).unwrap_or("").to_string()));
+            }
+
+            if self.head.len() >= MAX_HEAD_LEN {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "request head is too long"));
+            }
+
+            match self.stream.read(&mut buf) {
+                Ok(0) => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed")),
+                Ok(n) => self.head.extend_from_slice(&buf[..n]),
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    if self.accepted_at.elapsed() >= REQUEST_TIMEOUT {
+                        return Err(io::Error::new(io::ErrorKind::TimedOut, "request timed out"));
+                    }
+                    return Ok(None);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+/// Errors of a single connection, which do not affect the listener
+fn is_connection_error(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::ConnectionAborted | io::ErrorKind::ConnectionReset | io::ErrorKind::Interrupted
+    )
+}
+
+fn respond(stream: &mut TcpStream, status: &str, text: &str) -> Result<(), OAuthError> {
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        text.len(),
+        text
+    );
+    stream.write_all(response.as_bytes())?;
+    Ok(())
+}
+
+/// Creates 128 bits of state from the OS random number generator
+fn new_state() -> Result<SecretToken, OAuthError> {
+    let mut bytes = [0; 16];
+
+    OsRng::new()
+        .and_then(|mut rng| rng.try_fill_bytes(&mut bytes))
+        .map_err(|e| OAuthError::Io(format!("cannot generate state: {}", e)))?;
+
+    Ok(SecretToken::new(bytes.iter().map(|b| format!("{:02x}", b)).collect::<String>()))
+}
+
+/// Compares states without leaking the position of the first difference through timing
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len() && a.bytes().zip(b.bytes()).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
@@ -0,0 +1,325 @@
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use serde_json;
+
+use gitter::*;
+use ids::*;
+use models::*;
+use retry::*;
+
+/// Number of the newest room messages searched for an earlier attempt
+const RECENT_MESSAGES: i32 = 50;
+
+/// How much earlier than the item was queued Gitter may date its message,
+/// as the clocks of Gitter and the bot differ
+const CLOCK_SKEW_SECS: i64 = 60;
+
+/// Delivery status of an outbox item
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutboxStatus {
+    /// Waiting for delivery, possibly after failed attempts
+    Pending,
+    /// Posted to the room
+    Sent,
+    /// Given up, see `last_error`
+    Failed,
+}
+
+/// Message queued in the outbox
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OutboxItem {
+    /// ID given by the caller, unique among the queued messages
+    pub client_id: String,
+    pub room_id: RoomId,
+    pub text: String,
+    pub status: OutboxStatus,
+    pub queued_at: DateTime<Utc>,
+
+    /// Number of failed attempts
+    pub attempts: u32,
+
+    /// Time of the next attempt after a failure
+    pub retry_at: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+
+    /// ID of the posted message
+    pub message_id: Option<MessageId>,
+}
+
+/// Result of one `Outbox::deliver` pass
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DeliveryReport {
+    pub sent: usize,
+    pub failed: usize,
+
+    /// Items still waiting, e.g. because Gitter is unreachable
+    pub pending: usize,
+}
+
+/// Messages waiting for delivery, persisted to a JSON file.
+///
+/// Messages are delivered in the order they were queued within each room.
+/// A failed message blocks its room until it is sent or given up, while
+/// other rooms go on. The file is rewritten after every change, so a bot
+/// restarted during an outage delivers everything it queued before:
+///
+/// ```rust,ignore
+/// let outbox = Outbox::open("outbox.json")?;
+/// outbox.enqueue(&room_id, "build-1234", "Build #1234 failed")?;
+///
+/// // Periodically, e.g. every few seconds
+/// let report = outbox.deliver(&api)?;
+/// ```
+///
+/// The client ID makes enqueueing idempotent. Gitter ignores idempotency keys,
+/// so before a message whose earlier attempt failed is sent again, the newest
+/// messages of the room are searched for it: the response of a posted message
+/// may have been lost. The search cannot tell apart equal messages sent by the
+/// same user since the item was queued. Delivery is at least once: if the
+/// process stops between the send and the file update, the message is sent again
+#[derive(Debug)]
+pub struct Outbox {
+    path: PathBuf,
+    retry_policy: RetryPolicy,
+    items: Mutex<Vec<OutboxItem>>,
+    delivering: Mutex<()>,
+}
+
+impl Outbox {
+    /// Opens the outbox stored in the file, creating an empty one if there is no file
+    pub fn open<P>(path: P) -> io::Result<Outbox>
+        where P: Into<PathBuf>
+    {
+        let path = path.into();
+
+        let items = match fs::read(&path) {
+            Ok(ref data) if data.is_empty() => Vec::new(),
+            Ok(data) => serde_json::from_slice(&data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e),
+        };
+
+        Ok(Outbox {
+            path,
+            retry_policy: RetryPolicy::new().max_attempts(10).max_delay(Duration::from_secs(5 * 60)),
+            items: Mutex::new(items),
+            delivering: Mutex::new(()),
+        })
+    }
+
+    /// Changes how many times and how often failed messages are retried.
+    /// By default a message is given up after 10 attempts,
+    /// waiting up to 5 minutes between them
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Outbox {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Returns the path of the outbox file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Queues a message. Returns `false` without queueing it again
+    /// if a message with the same client ID is already in the outbox
+    pub fn enqueue<C, T>(&self, room_id: &RoomId, client_id: C, text: T) -> io::Result<bool>
+        where C: Into<String>,
+              T: Into<String>
+    {
+        let client_id = client_id.into();
+        let mut items = self.items.lock().unwrap();
+
+        if items.iter().any(|item| item.client_id == client_id) {
+            return Ok(false);
+        }
+
+        items.push(OutboxItem {
+            client_id,
+            room_id: room_id.clone(),
+            text: text.into(),
+            status: OutboxStatus::Pending,
+            queued_at: Utc::now(),
+            attempts: 0,
+            retry_at: None,
+            last_error: None,
+            message_id: None,
+        });
+
+        self.save(&items)?;
+        Ok(true)
+    }
+
+    /// Returns the item with the client ID
+    pub fn get(&self, client_id: &str) -> Option<OutboxItem> {
+        self.items.lock().unwrap().iter().find(|item| item.client_id == client_id).cloned()
+    }
+
+    /// Returns the status of the item with the client ID
+    pub fn status(&self, client_id: &str) -> Option<OutboxStatus> {
+        self.get(client_id).map(|item| item.status)
+    }
+
+    /// Returns all items in the order they were queued
+    pub fn items(&self) -> Vec<OutboxItem> {
+        self.items.lock().unwrap().clone()
+    }
+
+    /// Returns the number of items waiting for delivery
+    pub fn pending(&self) -> usize {
+        self.items.lock().unwrap().iter().filter(|item| item.status == OutboxStatus::Pending).count()
+    }
+
+    /// Removes sent and failed items. Their client IDs may be queued again afterwards
+    pub fn remove_finished(&self) -> io::Result<()> {
+        let mut items = self.items.lock().unwrap();
+        items.retain(|item| item.status == OutboxStatus::Pending);
+        self.save(&items)
+    }
+
+    /// Tries to send every pending message which is due, in order within each room
+    pub fn deliver(&self, api: &Gitter) -> io::Result<DeliveryReport> {
+        let _delivering = self.delivering.lock().unwrap();
+        let mut report = DeliveryReport::default();
+        let mut blocked_rooms = HashSet::new();
+
+        for pending in self.pending_items() {
+            if blocked_rooms.contains(&pending.room_id) {
+                continue;
+            }
+
+            if pending.retry_at.filter(|&retry_at| retry_at > Utc::now()).is_some() {
+                blocked_rooms.insert(pending.room_id);
+                continue;
+            }
+
+            let result = match self.find_sent(api, &pending) {
+                Ok(Some(message)) => Ok(message),
+                Ok(None) => api.send_message_with_idempotency_key(&pending.room_id, &pending.text, &pending.client_id),
+                Err(e) => Err(e),
+            };
+
+            let mut items = self.items.lock().unwrap();
+            let status = match items.iter_mut().find(|item| item.client_id == pending.client_id) {
+                Some(item) => self.update(item, result),
+                None => continue,
+            };
+            self.save(&items)?;
+
+            match status {
+                OutboxStatus::Sent => report.sent += 1,
+                OutboxStatus::Failed => report.failed += 1,
+                OutboxStatus::Pending => {
+                    blocked_rooms.insert(pending.room_id);
+                }
+            }
+        }
+
+        report.pending = self.pending();
+        Ok(report)
+    }
+
+    fn pending_items(&self) -> Vec<OutboxItem> {
+        self.items
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|item| item.status == OutboxStatus::Pending)
+            .cloned()
+            .collect()
+    }
+
+    /// Returns the message an earlier failed attempt posted, if any. It is a message
+    /// of the current user with the text of the item, sent after the item was queued
+    fn find_sent(&self, api: &Gitter, item: &OutboxItem) -> Result<Option<Message>, ApiError> {
+        if item.attempts == 0 {
+            return Ok(None);
+        }
+
+        let user = api.get_user()?;
+        let recent = Pagination {
+            skip: 0,
+            before_id: None,
+            after_id: None,
+            limit: RECENT_MESSAGES,
+            query: None,
+        };
+        let since = item.queued_at - ChronoDuration::seconds(CLOCK_SKEW_SECS);
+
+        Ok(api
+            .get_messages(&item.room_id, Some(recent))?
+            .into_iter()
+            .find(|message| message.from.id == user.id && message.text == item.text && message.sent >= since))
+    }
+
+    /// Records the result of an attempt and returns the new status
+    fn update(&self, item: &mut OutboxItem, result: Result<Message, ApiError>) -> OutboxStatus {
+        match result {
+            Ok(message) => {
+                item.status = OutboxStatus::Sent;
+                item.message_id = Some(message.id);
+                item.retry_at = None;
+                item.last_error = None;
+            }
+            Err(e) => {
+                item.attempts += 1;
+                item.last_error = Some(format!("{:?}", e));
+
+                if is_permanent(&e) || item.attempts >= self.retry_policy.attempts() {
+                    item.status = OutboxStatus::Failed;
+                    item.retry_at = None;
+                } else {
+                    let delay = self.retry_policy.delay(item.attempts, None);
+                    item.retry_at = Some(Utc::now() + ChronoDuration::milliseconds(millis(delay) as i64));
+                }
+            }
+        }
+
+        item.status
+    }
+
+    /// Writes the items to a temporary file, flushes it to disk and moves it
+    /// over the outbox file, so a crash never leaves a half written outbox
+    fn save(&self, items: &[OutboxItem]) -> io::Result<()> {
+        let data = serde_json::to_vec_pretty(items).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        let mut temp_path = self.path.clone().into_os_string();
+        temp_path.push(".tmp");
+
+        let mut file = File::create(&temp_path)?;
+        file.write_all(&data)?;
+        file.sync_all()?;
+        drop(file);
+
+        fs::rename(&temp_path, &self.path)?;
+        sync_dir(&self.path)
+    }
+}
+
+/// Flushes the directory entry of a renamed file, so the rename survives a crash
+#[cfg(unix)]
+fn sync_dir(path: &Path) -> io::Result<()> {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => File::open(dir)?.sync_all(),
+        _ => File::open(".")?.sync_all(),
+    }
+}
+
+/// Directories cannot be opened as files on other platforms
+#[cfg(not(unix))]
+fn sync_dir(_: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+/// Errors which retrying does not fix
+fn is_permanent(e: &ApiError) -> bool {
+    matches!(
+        *e,
+        ApiError::PermissionDenied(_) | ApiError::NotFound(_) | ApiError::RoomNotFound | ApiError::Credentials(_)
+    )
+}
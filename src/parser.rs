@@ -0,0 +1,315 @@
+use serde_json::Map;
+
+use models::*;
+
+/// Mentions, issue references and URLs found in a message text,
+/// in the same shape Gitter returns them in `Message`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MessageEntities {
+    /// List of @Mentions in the message
+    pub mentions: Vec<Mention>,
+
+    /// List of #Issues referenced in the message
+    pub issues: Vec<Issue>,
+
+    /// List of URLs present in the message
+    pub urls: Vec<Url>,
+}
+
+impl MessageEntities {
+    /// Fills user IDs of the mentions from the known users.
+    /// Gitter does the same for users which are on Gitter
+    pub fn resolve_mentions(&mut self, users: &[User]) {
+        for mention in self.mentions.iter_mut().filter(|m| m.group.is_none()) {
+            mention.user_id = users
+                .iter()
+                .find(|u| u.username.eq_ignore_ascii_case(&mention.screen_name))
+                .map(|u| u.id.clone());
+        }
+    }
+}
+
+/// Extracts mentions, issue references and URLs from the Markdown text of a message.
+///
+/// Works offline, so it can be used for outgoing and archived messages.
+/// User IDs of the mentions are not known, see `MessageEntities::resolve_mentions`
+pub fn parse_entities(text: &str) -> MessageEntities {
+    let text = mask_code(text);
+    let chars = text.chars().collect::<Vec<_>>();
+    let mut entities = MessageEntities::default();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let prev = if i > 0 { Some(chars[i - 1]) } else { None };
+        let at_boundary = is_boundary(prev);
+
+        if chars[i] == '\\' {
+            // Escaped character has no special meaning
+            i += 2;
+            continue;
+        }
+
+        if at_boundary && (starts_with(&chars, i, "http://") || starts_with(&chars, i, "https://")) {
+            let url = read_url(&chars, i);
+            i += url.chars().count();
+            push_url(&mut entities, url);
+            continue;
+        }
+
+        if chars[i] == '@' && at_boundary {
+            if starts_with(&chars, i + 1, "/all") && is_boundary(chars.get(i + 5).cloned()) {
+                push_mention(&mut entities, group_mention());
+                i += 5;
+                continue;
+            }
+
+            let name = read_while(&chars, i + 1, is_username_char);
+            if !name.is_empty() {
+                i += 1 + name.chars().count();
+                push_mention(&mut entities, user_mention(name));
+                continue;
+            }
+        }
+
+        if chars[i] == '#' && prev != Some('&') {
+            let number = read_while(&chars, i + 1, |c| c.is_ascii_digit());
+            let end = i + 1 + number.len();
+
+            if !number.is_empty() && is_boundary(chars.get(end).cloned()) {
+                let repo = if at_boundary { None } else { read_repo_before(&chars, i) };
+
+                if at_boundary || repo.is_some() {
+                    push_issue(&mut entities, issue(number, repo, None));
+                }
+
+                i = end;
+                continue;
+            }
+        }
+
+        i += 1;
+    }
+
+    entities
+}
+
+/// Replaces code spans and fenced code blocks with spaces,
+/// because Gitter does not look for entities in code
+fn mask_code(text: &str) -> String {
+    let mut masked = String::with_capacity(text.len());
+    let mut fence: Option<String> = None;
+
+    for line in text.split('\n') {
+        let trimmed = line.trim_start();
+
+        if let Some(open) = fence.clone() {
+            if trimmed.starts_with(&open) && trimmed.trim_end().chars().all(|c| c == '`' || c == '~') {
+                fence = None;
+            }
+            masked.push('\n');
+            continue;
+        }
+
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            let marker = trimmed.chars().next().unwrap();
+            fence = Some(trimmed.chars().take_while(|&c| c == marker).collect());
+            masked.push('\n');
+            continue;
+        }
+
+        masked.push_str(&mask_code_spans(line));
+        masked.push('\n');
+    }
+
+    masked.pop();
+    masked
+}
+
+fn mask_code_spans(line: &str) -> String {
+    let chars = line.chars().collect::<Vec<_>>();
+    let mut masked = String::with_capacity(line.len());
+    let mut i = 0;
+
+    while i < chars.len() {
+        if chars[i] == '\\' && i + 1 < chars.len() {
+            masked.push(chars[i]);
+            masked.push(chars[i + 1]);
+            i += 2;
+            continue;
+        }
+
+        if chars[i] != '`' {
+            masked.push(chars[i]);
+            i += 1;
+            continue;
+        }
+
+        let run = chars[i..].iter().take_while(|&&c| c == '`').count();
+
+        match closing_backticks(&chars, i + run, run) {
+            Some(end) => {
+                masked.push_str(&" ".repeat(end + run - i));
+                i = end + run;
+            }
+            None => {
+                masked.push_str(&"`".repeat(run));
+                i += run;
+            }
+        }
+    }
+
+    masked
+}
+
+/// Finds the backtick run of length `run` which closes a code span, starting at `start`
+pub(crate) fn closing_backticks(chars: &[char], start: usize, run: usize) -> Option<usize> {
+    (start..chars.len()).find(|&j| {
+        chars[j..].iter().take_while(|&&c| c == '`').count() == run && (j == 0 || chars[j - 1] != '`')
+    })
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Entities must not be glued to a word before or after them
+pub(crate) fn is_boundary(c: Option<char>) -> bool {
+    match c {
+        Some(c) => !is_word_char(c),
+        None => true,
+    }
+}
+
+pub(crate) fn is_username_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '-'
+}
+
+fn is_repo_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.'
+}
+
+pub(crate) fn starts_with(chars: &[char], i: usize, prefix: &str) -> bool {
+    prefix.chars().enumerate().all(|(j, c)| chars.get(i + j) == Some(&c))
+}
+
+pub(crate) fn read_while<F>(chars: &[char], start: usize, f: F) -> String
+    where F: Fn(char) -> bool
+{
+    chars.iter().skip(start).take_while(|&&c| f(c)).collect()
+}
+
+/// Reads `org/repo` which precedes `#` at `hash`
+fn read_repo_before(chars: &[char], hash: usize) -> Option<String> {
+    let start = (0..hash)
+        .rev()
+        .take_while(|&j| is_repo_char(chars[j]) || chars[j] == '/')
+        .last()?;
+
+    if start > 0 && is_word_char(chars[start - 1]) {
+        return None;
+    }
+
+    let repo = chars[start..hash].iter().collect::<String>();
+    let mut parts = repo.split('/');
+
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(org), Some(name), None) if !org.is_empty() && !name.is_empty() => Some(repo),
+        _ => None,
+    }
+}
+
+/// Reads URL, leaving out trailing punctuation and unbalanced parentheses
+pub(crate) fn read_url(chars: &[char], start: usize) -> String {
+    let mut url = String::new();
+    let mut depth = 0;
+
+    for &c in &chars[start..] {
+        match c {
+            c if c.is_whitespace() => break,
+            '<' | '>' | '"' | '`' => break,
+            '(' => depth += 1,
+            ')' if depth == 0 => break,
+            ')' => depth -= 1,
+            _ => {}
+        }
+        url.push(c);
+    }
+
+    let trimmed_len = url.trim_end_matches(|c| ".,;:!?*_'".contains(c)).len();
+    url.truncate(trimmed_len);
+    url
+}
+
+fn group_mention() -> Mention {
+    Mention {
+        screen_name: "all".to_string(),
+        user_id: None,
+        announcement: Some(true),
+        group: Some(true),
+        user_ids: Some(Vec::new()),
+        extra: Map::new(),
+    }
+}
+
+fn user_mention(screen_name: String) -> Mention {
+    Mention {
+        screen_name,
+        user_id: None,
+        announcement: None,
+        group: None,
+        user_ids: Some(Vec::new()),
+        extra: Map::new(),
+    }
+}
+
+fn issue(number: String, repo: Option<String>, is_pull_request: Option<bool>) -> Issue {
+    Issue {
+        number,
+        repo,
+        is_pull_request,
+        extra: Map::new(),
+    }
+}
+
+fn push_mention(entities: &mut MessageEntities, mention: Mention) {
+    if !entities.mentions.iter().any(|m| m.screen_name == mention.screen_name) {
+        entities.mentions.push(mention);
+    }
+}
+
+fn push_issue(entities: &mut MessageEntities, issue: Issue) {
+    if !entities.issues.iter().any(|i| i.number == issue.number && i.repo == issue.repo) {
+        entities.issues.push(issue);
+    }
+}
+
+/// GitHub issue and pull request URLs are references to issues as well
+fn push_url(entities: &mut MessageEntities, url: String) {
+    let mut url_type = None;
+
+    {
+        let path = url.trim_start_matches("https://").trim_start_matches("http://");
+        let parts = path.split('/').collect::<Vec<_>>();
+
+        if parts.len() == 5
+            && (parts[0] == "github.com" || parts[0] == "www.github.com")
+            && (parts[3] == "issues" || parts[3] == "pull")
+            && !parts[4].is_empty()
+            && parts[4].chars().all(|c| c.is_ascii_digit())
+        {
+            let is_pull_request = if parts[3] == "pull" { Some(true) } else { None };
+            let repo = format!("{}/{}", parts[1], parts[2]);
+
+            push_issue(entities, issue(parts[4].to_string(), Some(repo), is_pull_request));
+            url_type = Some("issue".to_string());
+        }
+    }
+
+    if !entities.urls.iter().any(|u| u.url == url) {
+        entities.urls.push(Url {
+            url,
+            url_type,
+            extra: Map::new(),
+        });
+    }
+}
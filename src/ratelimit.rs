@@ -0,0 +1,275 @@
+use std::collections::HashMap;
+use std::sync::{Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use ids::*;
+
+/// Token bucket: `messages` per `period` on average,
+/// with bursts of up to `burst` messages
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    interval: Duration,
+    burst: u32,
+}
+
+impl RateLimit {
+    /// Allows `messages` per `period`, all of them may be sent at once
+    pub fn new(messages: u32, period: Duration) -> RateLimit {
+        let messages = messages.max(1);
+
+        RateLimit {
+            interval: period / messages,
+            burst: messages,
+        }
+    }
+
+    /// Sets how many messages may be sent at once, after a pause
+    pub fn burst(mut self, burst: u32) -> RateLimit {
+        self.burst = burst.max(1);
+        self
+    }
+}
+
+/// What happens to a send over the limit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverLimit {
+    /// Wait until the limit allows it
+    Queue,
+    /// Fail with `ApiError::RateLimited`
+    Fail,
+}
+
+/// Limits of `send_message`, in every room and in all rooms together.
+///
+/// Messages to one room are sent one at a time in the order of the calls,
+/// even from several threads:
+///
+/// ```rust,ignore
+/// let limits = SendLimits::new()
+///     .global(RateLimit::new(10, Duration::from_secs(1)))
+///     .per_room(RateLimit::new(5, Duration::from_secs(5)).burst(2))
+///     .over_limit(OverLimit::Queue);
+///
+/// let api = Gitter::new(token)?.with_send_limits(limits);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SendLimits {
+    global: Option<RateLimit>,
+    per_room: Option<RateLimit>,
+    over_limit: OverLimit,
+}
+
+impl Default for SendLimits {
+    fn default() -> SendLimits {
+        SendLimits::new()
+    }
+}
+
+impl SendLimits {
+    /// Creates limits which allow everything and queue sends over the limit
+    pub fn new() -> SendLimits {
+        SendLimits {
+            global: None,
+            per_room: None,
+            over_limit: OverLimit::Queue,
+        }
+    }
+
+    /// Limits messages to all rooms together
+    pub fn global(mut self, limit: RateLimit) -> SendLimits {
+        self.global = Some(limit);
+        self
+    }
+
+    /// Limits messages to each room
+    pub fn per_room(mut self, limit: RateLimit) -> SendLimits {
+        self.per_room = Some(limit);
+        self
+    }
+
+    pub fn over_limit(mut self, over_limit: OverLimit) -> SendLimits {
+        self.over_limit = over_limit;
+        self
+    }
+}
+
+/// Token bucket kept as the time the next message is due (GCRA):
+/// a message may go when it is at most `burst - 1` intervals early
+#[derive(Debug)]
+struct Bucket {
+    limit: RateLimit,
+    due: Instant,
+}
+
+impl Bucket {
+    fn new(limit: RateLimit) -> Bucket {
+        Bucket {
+            limit,
+            due: Instant::now(),
+        }
+    }
+
+    /// Returns how long to wait until a message may go
+    fn wait(&self, now: Instant) -> Duration {
+        let allowed_at = self
+            .limit
+            .interval
+            .checked_mul(self.limit.burst - 1)
+            .and_then(|early| now.checked_add(early));
+
+        match allowed_at {
+            Some(allowed_at) if self.due > allowed_at => self.due - allowed_at,
+            // A burst too long to count is never used up
+            _ => Duration::default(),
+        }
+    }
+
+    /// Takes a token, possibly in advance
+    fn take(&mut self, now: Instant) {
+        self.due = self.due.max(now) + self.limit.interval;
+    }
+}
+
+/// Room bucket and the queue of sends to the room
+#[derive(Debug, Default)]
+struct Room {
+    bucket: Option<Bucket>,
+    next_ticket: u64,
+    serving: u64,
+}
+
+impl Room {
+    /// Determines whether nobody waits for the room and its bucket is full,
+    /// so a new room would behave the same
+    fn is_idle(&self, now: Instant) -> bool {
+        self.serving == self.next_ticket && self.bucket.iter().all(|bucket| bucket.due <= now)
+    }
+}
+
+#[derive(Debug)]
+struct State {
+    global: Option<Bucket>,
+    rooms: HashMap<RoomId, Room>,
+}
+
+/// Applies `SendLimits`, shared by the clones of a client
+#[derive(Debug)]
+pub(crate) struct SendLimiter {
+    limits: SendLimits,
+    state: Mutex<State>,
+    turn: Condvar,
+}
+
+impl SendLimiter {
+    pub fn new(limits: SendLimits) -> SendLimiter {
+        SendLimiter {
+            state: Mutex::new(State {
+                global: limits.global.map(Bucket::new),
+                rooms: HashMap::new(),
+            }),
+            limits,
+            turn: Condvar::new(),
+        }
+    }
+
+    /// Waits for the turn of the send in the room and for the limits.
+    /// The next send to the room waits until the permit is dropped.
+    /// Fails with the time to wait if sends over the limit fail
+    pub fn acquire(&self, room_id: &RoomId) -> Result<Permit<'_>, Duration> {
+        let mut guard = self.state.lock().unwrap();
+
+        let ticket = {
+            let per_room = self.limits.per_room;
+            let room = guard.rooms.entry(room_id.clone()).or_insert_with(|| Room {
+                bucket: per_room.map(Bucket::new),
+                ..Room::default()
+            });
+            room.next_ticket += 1;
+            room.next_ticket - 1
+        };
+
+        while guard.rooms[room_id].serving != ticket {
+            guard = self.turn.wait(guard).unwrap();
+        }
+
+        drop(guard);
+
+        // Dropping the permit gives the turn to the next send
+        let permit = Permit {
+            limiter: self,
+            room_id: room_id.clone(),
+        };
+
+        permit.take()?;
+        Ok(permit)
+    }
+
+    /// Waits for the limits to allow one more message to the room
+    fn take(&self, room_id: &RoomId) -> Result<(), Duration> {
+        let wait = {
+            let now = Instant::now();
+            let mut guard = self.state.lock().unwrap();
+            let state = &mut *guard;
+            let room = state.rooms.get_mut(room_id).unwrap();
+
+            let wait = state
+                .global
+                .iter()
+                .chain(room.bucket.iter())
+                .map(|bucket| bucket.wait(now))
+                .max()
+                .unwrap_or_default();
+
+            if wait == Duration::default() || self.limits.over_limit == OverLimit::Queue {
+                for bucket in state.global.iter_mut().chain(room.bucket.iter_mut()) {
+                    bucket.take(now);
+                }
+            }
+
+            wait
+        };
+
+        if wait > Duration::default() && self.limits.over_limit == OverLimit::Fail {
+            return Err(wait);
+        }
+
+        // Tokens are taken in advance, so other rooms can go on while this one waits
+        thread::sleep(wait);
+
+        Ok(())
+    }
+
+    /// Gives the turn to the next send and forgets idle rooms
+    fn release(&self, room_id: &RoomId) {
+        let mut state = self.state.lock().unwrap();
+
+        if let Some(room) = state.rooms.get_mut(room_id) {
+            room.serving += 1;
+        }
+
+        let now = Instant::now();
+        state.rooms.retain(|_, room| !room.is_idle(now));
+
+        self.turn.notify_all();
+    }
+}
+
+/// Turn of a send in its room, passed to the next send when dropped
+pub(crate) struct Permit<'a> {
+    limiter: &'a SendLimiter,
+    room_id: RoomId,
+}
+
+impl<'a> Permit<'a> {
+    /// Waits for the limits to allow one more message, keeping the turn
+    pub fn take(&self) -> Result<(), Duration> {
+        self.limiter.take(&self.room_id)
+    }
+}
+
+impl<'a> Drop for Permit<'a> {
+    fn drop(&mut self) {
+        self.limiter.release(&self.room_id);
+    }
+}
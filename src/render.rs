@@ -0,0 +1,846 @@
+use builder::Fence;
+use models::*;
+use parser::{closing_backticks, is_boundary, is_username_char, read_url, read_while, starts_with};
+
+const ANSI_RESET: &str = "\x1b[0m";
+
+/// Common emoji shortcodes and their Unicode characters
+const EMOJI: &[(&str, &str)] = &[
+    ("+1", "👍"),
+    ("-1", "👎"),
+    ("100", "💯"),
+    ("blush", "😊"),
+    ("bug", "🐛"),
+    ("clap", "👏"),
+    ("coffee", "☕"),
+    ("confused", "😕"),
+    ("cry", "😢"),
+    ("eyes", "👀"),
+    ("fire", "🔥"),
+    ("grin", "😁"),
+    ("heart", "❤️"),
+    ("heavy_check_mark", "✔️"),
+    ("joy", "😂"),
+    ("laughing", "😆"),
+    ("ok_hand", "👌"),
+    ("pray", "🙏"),
+    ("rocket", "🚀"),
+    ("slightly_smiling_face", "🙂"),
+    ("smile", "😄"),
+    ("smiley", "😃"),
+    ("sob", "😭"),
+    ("sparkles", "✨"),
+    ("star", "⭐"),
+    ("sunglasses", "😎"),
+    ("sweat_smile", "😅"),
+    ("tada", "🎉"),
+    ("thinking", "🤔"),
+    ("thumbsdown", "👎"),
+    ("thumbsup", "👍"),
+    ("warning", "⚠️"),
+    ("wave", "👋"),
+    ("white_check_mark", "✅"),
+    ("wink", "😉"),
+    ("x", "❌"),
+];
+
+/// Converts messages to readable plain text or ANSI colored terminal output.
+///
+/// ```rust,ignore
+/// let renderer = Renderer::ansi().width(80);
+///
+/// for message in api.get_messages(&room_id, None)? {
+///     println!("{}: {}", message.sender_name(), renderer.render(&message));
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Renderer {
+    ansi: bool,
+    width: Option<usize>,
+}
+
+impl Renderer {
+    /// Creates renderer which produces plain text
+    pub fn plain() -> Renderer {
+        Renderer {
+            ansi: false,
+            width: None,
+        }
+    }
+
+    /// Creates renderer which produces text with ANSI escape codes
+    pub fn ansi() -> Renderer {
+        Renderer {
+            ansi: true,
+            width: None,
+        }
+    }
+
+    /// Wraps lines at `width` characters. Code blocks are never wrapped
+    pub fn width(mut self, width: usize) -> Renderer {
+        self.width = Some(width);
+        self
+    }
+
+    /// Renders message from its HTML, or from its text if HTML is absent
+    pub fn render(&self, message: &Message) -> String {
+        if message.html.is_empty() {
+            self.render_text(&message.text)
+        } else {
+            self.render_html(&message.html)
+        }
+    }
+
+    /// Renders Gitter message HTML
+    pub fn render_html(&self, html: &str) -> String {
+        let mut state = RenderState::new(self);
+
+        for token in tokenize(html) {
+            state.handle(token);
+        }
+
+        state.finish()
+    }
+
+    /// Renders raw message Markdown: code blocks, inline code,
+    /// links, mentions, quotes and emoji shortcodes
+    pub fn render_text(&self, text: &str) -> String {
+        let mut state = RenderState::new(self);
+
+        for token in markdown_tokens(text) {
+            state.handle(token);
+        }
+
+        state.finish()
+    }
+}
+
+/// Replaces known `:shortcode:` emoji with Unicode characters
+pub fn replace_emoji_shortcodes(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find(':') {
+        result.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+
+        let emoji = after.find(':').and_then(|end| {
+            let name = &after[..end];
+            EMOJI.iter().find(|&&(n, _)| n == name).map(|&(_, e)| (e, end))
+        });
+
+        match emoji {
+            Some((emoji, end)) => {
+                result.push_str(emoji);
+                rest = &after[end + 1..];
+            }
+            None => {
+                result.push(':');
+                rest = after;
+            }
+        }
+    }
+
+    result.push_str(rest);
+    result
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Start(String, Vec<(String, String)>),
+    End(String),
+    Text(String),
+}
+
+fn attr<'a>(attrs: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    attrs.iter().find(|a| a.0 == name).map(|a| a.1.as_str())
+}
+
+/// Splits HTML into tags and decoded text
+fn tokenize(html: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut rest = html;
+
+    while !rest.is_empty() {
+        if rest.starts_with("<!--") {
+            rest = rest.find("-->").map_or("", |end| &rest[end + 3..]);
+            continue;
+        }
+
+        if rest.starts_with('<') {
+            if let Some(end) = rest.find('>') {
+                let tag = &rest[1..end];
+                rest = &rest[end + 1..];
+
+                if tag.starts_with('/') {
+                    tokens.push(Token::End(tag.trim_start_matches('/').trim().to_lowercase()));
+                } else {
+                    let self_closing = tag.ends_with('/');
+                    let tag = tag.trim_end_matches('/');
+                    let name_len = tag.find(char::is_whitespace).unwrap_or(tag.len());
+                    let name = tag[..name_len].to_lowercase();
+
+                    tokens.push(Token::Start(name.clone(), parse_attrs(&tag[name_len..])));
+                    if self_closing {
+                        tokens.push(Token::End(name));
+                    }
+                }
+                continue;
+            }
+        }
+
+        let first_len = rest.chars().next().map_or(0, char::len_utf8);
+        let end = rest[first_len..].find('<').map_or(rest.len(), |i| i + first_len);
+        tokens.push(Token::Text(decode_entities(&rest[..end])));
+        rest = &rest[end..];
+    }
+
+    tokens
+}
+
+fn start(name: &str) -> Token {
+    Token::Start(name.to_string(), Vec::new())
+}
+
+fn end(name: &str) -> Token {
+    Token::End(name.to_string())
+}
+
+/// Converts message Markdown to the tokens of the HTML Gitter renders for it
+fn markdown_tokens(text: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut paragraph = Vec::new();
+    let mut quote = Vec::new();
+    let mut lines = text.split('\n');
+
+    while let Some(line) = lines.next() {
+        let trimmed = line.trim_start();
+
+        if !trimmed.starts_with('>') {
+            push_quote(&mut tokens, &mut quote);
+        }
+
+        if let Some(fence) = Fence::open(line) {
+            push_paragraph(&mut tokens, &mut paragraph);
+
+            // An unclosed code block runs to the end of the text
+            let code = lines.by_ref().take_while(|line| !fence.is_closed_by(line)).collect::<Vec<_>>();
+            tokens.push(start("pre"));
+            tokens.push(Token::Text(code.join("\n")));
+            tokens.push(end("pre"));
+        } else if let Some(content) = trimmed.strip_prefix('>') {
+            push_paragraph(&mut tokens, &mut paragraph);
+            quote.push(content.strip_prefix(' ').unwrap_or(content));
+        } else if trimmed.is_empty() {
+            push_paragraph(&mut tokens, &mut paragraph);
+        } else {
+            paragraph.push(line);
+        }
+    }
+
+    push_quote(&mut tokens, &mut quote);
+    push_paragraph(&mut tokens, &mut paragraph);
+
+    tokens
+}
+
+/// Lines of a paragraph are separated by line breaks, like Gitter does
+fn push_paragraph(tokens: &mut Vec<Token>, lines: &mut Vec<&str>) {
+    if lines.is_empty() {
+        return;
+    }
+
+    tokens.push(start("p"));
+    for (i, line) in lines.drain(..).enumerate() {
+        if i > 0 {
+            tokens.push(start("br"));
+        }
+        inline_tokens(line, tokens);
+    }
+    tokens.push(end("p"));
+}
+
+/// Quotes may contain any Markdown, including other quotes
+fn push_quote(tokens: &mut Vec<Token>, lines: &mut Vec<&str>) {
+    if lines.is_empty() {
+        return;
+    }
+
+    tokens.push(start("blockquote"));
+    tokens.extend(markdown_tokens(&lines.join("\n")));
+    tokens.push(end("blockquote"));
+    lines.clear();
+}
+
+/// Converts escapes, code spans, links, URLs and mentions of a line
+fn inline_tokens(line: &str, tokens: &mut Vec<Token>) {
+    let chars = line.chars().collect::<Vec<_>>();
+    let mut text = String::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let at_boundary = is_boundary(if i > 0 { Some(chars[i - 1]) } else { None });
+        let mut inline = Vec::new();
+
+        if chars[i] == '\\' && chars.get(i + 1).filter(|c| c.is_ascii_punctuation()).is_some() {
+            text.push(chars[i + 1]);
+            i += 2;
+            continue;
+        }
+
+        if chars[i] == '`' {
+            let run = chars[i..].iter().take_while(|&&c| c == '`').count();
+
+            match closing_backticks(&chars, i + run, run) {
+                Some(close) => {
+                    let code = chars[i + run..close].iter().collect::<String>();
+                    inline.push(start("code"));
+                    inline.push(Token::Text(code.trim().to_string()));
+                    inline.push(end("code"));
+                    i = close + run;
+                }
+                None => {
+                    text.push_str(&"`".repeat(run));
+                    i += run;
+                    continue;
+                }
+            }
+        } else if let Some((label, href, link_end)) = read_link(&chars, i) {
+            inline.push(Token::Start("a".to_string(), vec![("href".to_string(), href)]));
+            inline.push(Token::Text(label));
+            inline.push(end("a"));
+            i = link_end;
+        } else if at_boundary && (starts_with(&chars, i, "http://") || starts_with(&chars, i, "https://")) {
+            let url = read_url(&chars, i);
+            i += url.chars().count();
+            inline.push(Token::Start("a".to_string(), vec![("href".to_string(), url.clone())]));
+            inline.push(Token::Text(url));
+            inline.push(end("a"));
+        } else if chars[i] == '@' && at_boundary {
+            let (link_type, name) = if starts_with(&chars, i + 1, "/all") && is_boundary(chars.get(i + 5).cloned()) {
+                ("groupmention", "/all".to_string())
+            } else {
+                ("mention", read_while(&chars, i + 1, is_username_char))
+            };
+
+            if !name.is_empty() {
+                i += 1 + name.chars().count();
+                inline.push(Token::Start("span".to_string(), vec![("data-link-type".to_string(), link_type.to_string())]));
+                inline.push(Token::Text(format!("@{}", name)));
+                inline.push(end("span"));
+            }
+        }
+
+        if inline.is_empty() {
+            text.push(chars[i]);
+            i += 1;
+            continue;
+        }
+
+        if !text.is_empty() {
+            tokens.push(Token::Text(text.clone()));
+            text.clear();
+        }
+        tokens.extend(inline);
+    }
+
+    if !text.is_empty() {
+        tokens.push(Token::Text(text));
+    }
+}
+
+/// Reads `[label](href)` at `start`, returns the label, the href and the end of the link
+fn read_link(chars: &[char], start: usize) -> Option<(String, String, usize)> {
+    if chars[start] != '[' {
+        return None;
+    }
+
+    let label_end = (start + 1..chars.len()).find(|&j| chars[j] == ']')?;
+    if chars.get(label_end + 1) != Some(&'(') {
+        return None;
+    }
+    let href_end = (label_end + 2..chars.len()).find(|&j| chars[j] == ')')?;
+
+    let label = chars[start + 1..label_end].iter().collect::<String>();
+    let href = chars[label_end + 2..href_end].iter().collect::<String>();
+
+    if label.is_empty() || href.trim().is_empty() {
+        return None;
+    }
+
+    Some((label, href.trim().to_string(), href_end + 1))
+}
+
+/// Removes control characters except line breaks and tabs, so message
+/// content cannot inject terminal escape sequences
+fn strip_control_chars(text: &str) -> String {
+    text.chars().filter(|&c| !c.is_control() || c == '\n' || c == '\t').collect()
+}
+
+fn parse_attrs(text: &str) -> Vec<(String, String)> {
+    let mut attrs = Vec::new();
+    let mut rest = text.trim_start();
+
+    while !rest.is_empty() {
+        let name_len = rest.find(|c: char| c == '=' || c.is_whitespace()).unwrap_or(rest.len());
+        let name = rest[..name_len].to_lowercase();
+        rest = rest[name_len..].trim_start();
+
+        let mut value = String::new();
+        if rest.starts_with('=') {
+            rest = rest[1..].trim_start();
+
+            let quote = rest.chars().next().filter(|&c| c == '"' || c == '\'');
+            let (raw, remaining) = match quote {
+                Some(q) => {
+                    let end = rest[1..].find(q).map_or(rest.len(), |i| i + 1);
+                    (&rest[1..end], rest.get(end + 1..).unwrap_or(""))
+                }
+                None => {
+                    let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+                    (&rest[..end], &rest[end..])
+                }
+            };
+
+            value = decode_entities(raw);
+            rest = remaining.trim_start();
+        }
+
+        if !name.is_empty() {
+            attrs.push((name, value));
+        }
+    }
+
+    attrs
+}
+
+fn decode_entities(text: &str) -> String {
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let entity = rest.find(';').filter(|&end| end <= 10).and_then(|end| {
+            let name = &rest[1..end];
+            let c = match name {
+                "amp" => Some('&'),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                "nbsp" => Some(' '),
+                _ if name.starts_with("#x") || name.starts_with("#X") => {
+                    u32::from_str_radix(&name[2..], 16).ok().and_then(::std::char::from_u32)
+                }
+                _ if name.starts_with('#') => name[1..].parse().ok().and_then(::std::char::from_u32),
+                _ => None,
+            };
+            c.map(|c| (c, end))
+        });
+
+        match entity {
+            Some((c, end)) => {
+                decoded.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+
+    decoded.push_str(rest);
+    decoded
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct Style {
+    bold: bool,
+    italic: bool,
+    strike: bool,
+    code: bool,
+    link: bool,
+    mention: bool,
+    issue: bool,
+}
+
+impl Style {
+    fn ansi_codes(&self) -> String {
+        let mut codes = Vec::new();
+
+        if self.bold {
+            codes.push("1");
+        }
+        if self.italic {
+            codes.push("3");
+        }
+        if self.link {
+            codes.push("4");
+            codes.push("34");
+        }
+        if self.strike {
+            codes.push("9");
+        }
+        if self.code {
+            codes.push("36");
+        }
+        if self.mention {
+            codes.push("1");
+            codes.push("33");
+        }
+        if self.issue {
+            codes.push("35");
+        }
+
+        if codes.is_empty() {
+            String::new()
+        } else {
+            format!("\x1b[{}m", codes.join(";"))
+        }
+    }
+}
+
+/// Piece of a word with a single style
+#[derive(Debug, Clone)]
+struct Piece {
+    text: String,
+    style: Style,
+}
+
+/// Inline content of a block: words made of styled pieces,
+/// `None` stands for a hard line break
+type Words = Vec<Option<Vec<Piece>>>;
+
+struct RenderState<'a> {
+    renderer: &'a Renderer,
+    lines: Vec<String>,
+    words: Words,
+    space_pending: bool,
+    style_stack: Vec<(String, Style)>,
+    prefixes: Vec<String>,
+    list_counters: Vec<Option<usize>>,
+    item_marker: Option<String>,
+    link: Option<(String, String)>,
+    pre: Option<String>,
+    blank_line_pending: bool,
+}
+
+impl<'a> RenderState<'a> {
+    fn new(renderer: &'a Renderer) -> RenderState<'a> {
+        RenderState {
+            renderer,
+            lines: Vec::new(),
+            words: Vec::new(),
+            space_pending: false,
+            style_stack: Vec::new(),
+            prefixes: Vec::new(),
+            list_counters: Vec::new(),
+            item_marker: None,
+            link: None,
+            pre: None,
+            blank_line_pending: false,
+        }
+    }
+
+    fn style(&self) -> Style {
+        self.style_stack.last().map_or_else(Style::default, |s| s.1)
+    }
+
+    fn push_style<F>(&mut self, tag: &str, f: F)
+        where F: FnOnce(&mut Style)
+    {
+        let mut style = self.style();
+        f(&mut style);
+        self.style_stack.push((tag.to_string(), style));
+    }
+
+    fn pop_style(&mut self, tag: &str) {
+        if let Some(pos) = self.style_stack.iter().rposition(|s| s.0 == tag) {
+            self.style_stack.truncate(pos);
+        }
+    }
+
+    fn handle(&mut self, token: Token) {
+        match token {
+            Token::Text(text) => self.text(&text),
+            Token::Start(name, attrs) => self.start(&name, &attrs),
+            Token::End(name) => self.end(&name),
+        }
+    }
+
+    fn text(&mut self, text: &str) {
+        let text = &strip_control_chars(text);
+
+        if let Some(ref mut pre) = self.pre {
+            pre.push_str(text);
+            return;
+        }
+
+        if let Some(ref mut link) = self.link {
+            link.1.push_str(text);
+        }
+
+        let text = replace_emoji_shortcodes(text);
+        let style = self.style();
+
+        for (i, part) in text.split(char::is_whitespace).enumerate() {
+            if i > 0 {
+                self.space_pending = true;
+            }
+            if part.is_empty() {
+                continue;
+            }
+
+            let piece = Piece {
+                text: part.to_string(),
+                style,
+            };
+
+            match self.words.last_mut() {
+                Some(&mut Some(ref mut word)) if !self.space_pending => word.push(piece),
+                _ => self.words.push(Some(vec![piece])),
+            }
+            self.space_pending = false;
+        }
+    }
+
+    fn start(&mut self, name: &str, attrs: &[(String, String)]) {
+        match name {
+            "br" => {
+                self.words.push(None);
+                self.space_pending = false;
+            }
+            "p" | "div" | "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                self.flush_block();
+                if name.starts_with('h') {
+                    self.push_style(name, |s| s.bold = true);
+                }
+            }
+            "strong" | "b" => self.push_style(name, |s| s.bold = true),
+            "em" | "i" => self.push_style(name, |s| s.italic = true),
+            "del" | "s" => self.push_style(name, |s| s.strike = true),
+            "code" if self.pre.is_none() => self.push_style(name, |s| s.code = true),
+            "a" => {
+                let href = attr(attrs, "href").unwrap_or("").to_string();
+                self.link = Some((href, String::new()));
+                self.push_style(name, |s| s.link = true);
+            }
+            "span" => match attr(attrs, "data-link-type") {
+                Some("mention") | Some("groupmention") => self.push_style(name, |s| s.mention = true),
+                Some("issue") | Some("pr") | Some("commit") => self.push_style(name, |s| s.issue = true),
+                _ => self.push_style(name, |_| {}),
+            },
+            "img" => {
+                let alt = attr(attrs, "alt").or_else(|| attr(attrs, "title")).unwrap_or("");
+                self.text(alt);
+            }
+            "pre" => {
+                self.flush_block();
+                self.pre = Some(String::new());
+            }
+            "blockquote" => {
+                self.flush_block();
+                self.push_blank_line();
+                let prefix = if self.renderer.ansi { "\x1b[2m│\x1b[0m " } else { "> " };
+                self.prefixes.push(prefix.to_string());
+            }
+            "ul" | "ol" => {
+                self.flush_block();
+                self.list_counters.push(if name == "ol" { Some(0) } else { None });
+            }
+            "li" => {
+                self.flush_lines();
+                let marker = match self.list_counters.last_mut() {
+                    Some(&mut Some(ref mut n)) => {
+                        *n += 1;
+                        format!("{}. ", n)
+                    }
+                    _ => "• ".to_string(),
+                };
+                self.item_marker = Some(marker);
+            }
+            _ => {}
+        }
+    }
+
+    fn end(&mut self, name: &str) {
+        match name {
+            "p" | "div" => self.flush_block(),
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                self.pop_style(name);
+                self.flush_block();
+            }
+            "strong" | "b" | "em" | "i" | "del" | "s" | "span" => self.pop_style(name),
+            "code" if self.pre.is_none() => self.pop_style(name),
+            "a" => {
+                self.pop_style(name);
+
+                if let Some((href, text)) = self.link.take() {
+                    if !href.is_empty() && href.trim() != text.trim() {
+                        let style = self.style();
+                        self.words.push(Some(vec![Piece {
+                            text: format!("({})", strip_control_chars(&href)),
+                            style,
+                        }]));
+                    }
+                }
+            }
+            "pre" => {
+                if let Some(code) = self.pre.take() {
+                    self.code_block(&code);
+                }
+            }
+            "blockquote" => {
+                self.flush_block();
+                self.prefixes.pop();
+            }
+            "ul" | "ol" => {
+                self.flush_lines();
+                self.list_counters.pop();
+                if self.list_counters.is_empty() {
+                    self.blank_line_pending = true;
+                }
+            }
+            "li" => self.flush_lines(),
+            _ => {}
+        }
+    }
+
+    /// Returns prefix for the first line of a block and for the following lines
+    fn line_prefixes(&mut self) -> (String, String) {
+        let base = self.prefixes.concat();
+        let indent = "  ".repeat(self.list_counters.len().saturating_sub(1));
+
+        match self.item_marker.take() {
+            Some(marker) => (
+                format!("{}{}{}", base, indent, marker),
+                format!("{}{}{}", base, indent, " ".repeat(marker.chars().count())),
+            ),
+            None if !self.list_counters.is_empty() => {
+                let continuation = format!("{}{}  ", base, indent);
+                (continuation.clone(), continuation)
+            }
+            None => (base.clone(), base),
+        }
+    }
+
+    /// Separates blocks with a line which keeps the quote prefixes
+    fn push_blank_line(&mut self) {
+        if self.blank_line_pending && !self.lines.is_empty() {
+            self.lines.push(self.prefixes.concat().trim_end().to_string());
+        }
+        self.blank_line_pending = false;
+    }
+
+    fn push_line(&mut self, line: String) {
+        self.push_blank_line();
+        self.lines.push(line);
+    }
+
+    fn code_block(&mut self, code: &str) {
+        self.flush_lines();
+        self.blank_line_pending = true;
+
+        let (first, rest) = self.line_prefixes();
+        let code = code.trim_end_matches('\n');
+
+        for (i, line) in code.split('\n').enumerate() {
+            let prefix = if i == 0 { &first } else { &rest };
+            let line = if self.renderer.ansi {
+                format!("{}    \x1b[36m{}{}", prefix, line, ANSI_RESET)
+            } else {
+                format!("{}    {}", prefix, line)
+            };
+            self.push_line(line);
+        }
+
+        self.blank_line_pending = true;
+    }
+
+    /// Writes pending inline content as lines
+    fn flush_lines(&mut self) {
+        // Line breaks at the end of a block would only add empty lines
+        while let Some(&None) = self.words.last() {
+            self.words.pop();
+        }
+
+        if self.words.is_empty() {
+            return;
+        }
+
+        let words = self.words.drain(..).collect::<Words>();
+        let (first, rest) = self.line_prefixes();
+        let width = self.renderer.width;
+
+        let mut lines: Vec<Vec<Vec<Piece>>> = vec![Vec::new()];
+        let mut line_width = 0;
+
+        for word in words {
+            let word = match word {
+                Some(word) => word,
+                None => {
+                    lines.push(Vec::new());
+                    line_width = 0;
+                    continue;
+                }
+            };
+
+            let prefix_width = if lines.len() == 1 { first.chars().count() } else { rest.chars().count() };
+            let word_width = word.iter().map(|p| p.text.chars().count()).sum::<usize>();
+            let space = if line_width > 0 { 1 } else { 0 };
+
+            if let Some(width) = width {
+                if line_width > 0 && prefix_width + line_width + space + word_width > width {
+                    lines.push(Vec::new());
+                    line_width = 0;
+                }
+            }
+
+            line_width += if line_width > 0 { 1 } else { 0 } + word_width;
+            lines.last_mut().unwrap().push(word);
+        }
+
+        for (i, line) in lines.into_iter().enumerate() {
+            let prefix = if i == 0 { &first } else { &rest };
+            let text = line
+                .iter()
+                .map(|word| word.iter().map(|p| self.piece(p)).collect::<String>())
+                .collect::<Vec<_>>()
+                .join(" ");
+
+            self.push_line(format!("{}{}", prefix, text).trim_end().to_string());
+        }
+    }
+
+    /// Writes pending inline content and separates the next block with a blank line
+    fn flush_block(&mut self) {
+        let had_content = self.words.iter().any(|w| w.is_some());
+        self.flush_lines();
+
+        if had_content {
+            self.blank_line_pending = true;
+        }
+    }
+
+    fn piece(&self, piece: &Piece) -> String {
+        let codes = if self.renderer.ansi { piece.style.ansi_codes() } else { String::new() };
+
+        if codes.is_empty() {
+            piece.text.clone()
+        } else {
+            format!("{}{}{}", codes, piece.text, ANSI_RESET)
+        }
+    }
+
+    fn finish(mut self) -> String {
+        if let Some(code) = self.pre.take() {
+            self.code_block(&code);
+        }
+        self.flush_lines();
+
+        self.lines.join("\n")
+    }
+}
@@ -0,0 +1,144 @@
+use std::time::Duration;
+use rand::{thread_rng, Rng};
+
+use middleware::HttpMethod;
+
+/// Statuses which usually mean that the same request may succeed later
+const RETRYABLE_STATUSES: &[u16] = &[408, 429, 500, 502, 503, 504];
+
+/// Methods which are safe to send again when the response was lost
+const RETRYABLE_METHODS: &[HttpMethod] = &[HttpMethod::Get, HttpMethod::Put, HttpMethod::Delete];
+
+/// How failed requests are retried.
+///
+/// Requests are retried after connection errors and retryable statuses,
+/// waiting `base_delay * 2^n` (at most `max_delay`) between attempts.
+/// GET, PUT and DELETE requests are retried by default, see `retry_methods`.
+/// POST requests are also retried when the caller provides an idempotency key.
+/// A retried DELETE answered with 404 counts as a success, since an earlier
+/// attempt whose response was lost may have deleted the resource:
+///
+/// ```rust,ignore
+/// let policy = RetryPolicy::new()
+///     .max_attempts(5)
+///     .base_delay(Duration::from_millis(500));
+///
+/// let api = Gitter::new(token)?.with_retry_policy(policy);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    base_delay: Duration,
+    max_delay: Duration,
+    jitter: bool,
+    retryable_statuses: Vec<u16>,
+    retry_methods: Vec<HttpMethod>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> RetryPolicy {
+        RetryPolicy::new()
+    }
+}
+
+impl RetryPolicy {
+    /// Creates default policy: 3 attempts, 250ms base delay, 10s maximum delay,
+    /// with jitter, retrying 408, 429, 500, 502, 503 and 504 statuses
+    /// of GET, PUT and DELETE requests
+    pub fn new() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(250),
+            max_delay: Duration::from_secs(10),
+            jitter: true,
+            retryable_statuses: RETRYABLE_STATUSES.to_vec(),
+            retry_methods: RETRYABLE_METHODS.to_vec(),
+        }
+    }
+
+    /// Creates policy which never retries
+    pub fn none() -> RetryPolicy {
+        RetryPolicy::new().max_attempts(1)
+    }
+
+    /// Sets the number of attempts, including the first one
+    pub fn max_attempts(mut self, max_attempts: u32) -> RetryPolicy {
+        self.max_attempts = max_attempts.max(1);
+        self
+    }
+
+    /// Sets the delay before the first retry, it doubles with each next retry
+    pub fn base_delay(mut self, base_delay: Duration) -> RetryPolicy {
+        self.base_delay = base_delay;
+        self
+    }
+
+    /// Sets the longest delay between attempts
+    pub fn max_delay(mut self, max_delay: Duration) -> RetryPolicy {
+        self.max_delay = max_delay;
+        self
+    }
+
+    /// Randomizes delays between a half and the full delay,
+    /// so many clients do not retry at the same moment
+    pub fn jitter(mut self, jitter: bool) -> RetryPolicy {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Sets HTTP statuses which are retried
+    pub fn retryable_statuses<I>(mut self, statuses: I) -> RetryPolicy
+        where I: IntoIterator<Item = u16>
+    {
+        self.retryable_statuses = statuses.into_iter().collect();
+        self
+    }
+
+    /// Sets HTTP methods which are retried. Add `HttpMethod::Post` only if
+    /// posting the same request twice does no harm
+    pub fn retry_methods<I>(mut self, methods: I) -> RetryPolicy
+        where I: IntoIterator<Item = HttpMethod>
+    {
+        self.retry_methods = methods.into_iter().collect();
+        self
+    }
+
+    /// Returns the number of attempts, including the first one
+    pub fn attempts(&self) -> u32 {
+        self.max_attempts
+    }
+
+    /// Determines whether a response with `status` should be retried
+    pub fn is_retryable_status(&self, status: u16) -> bool {
+        self.retryable_statuses.contains(&status)
+    }
+
+    /// Determines whether requests with `method` should be retried
+    pub fn is_retryable_method(&self, method: HttpMethod) -> bool {
+        self.retry_methods.contains(&method)
+    }
+
+    /// Returns the delay before retry number `retry`, starting from 1.
+    /// `retry_after` is the delay the server asked for, if any
+    pub fn delay(&self, retry: u32, retry_after: Option<Duration>) -> Duration {
+        let base = millis(self.base_delay);
+        let max = millis(self.max_delay);
+
+        let exponent = retry.saturating_sub(1).min(32);
+        let mut delay = base.saturating_mul(1 << exponent).min(max);
+
+        if self.jitter && delay > 1 {
+            delay = thread_rng().gen_range(delay / 2, delay + 1);
+        }
+
+        let delay = Duration::from_millis(delay);
+        match retry_after {
+            Some(retry_after) if retry_after > delay => retry_after.min(self.max_delay),
+            _ => delay,
+        }
+    }
+}
+
+pub(crate) fn millis(duration: Duration) -> u64 {
+    duration.as_secs() * 1000 + u64::from(duration.subsec_millis())
+}
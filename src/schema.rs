@@ -0,0 +1,217 @@
+use std::error::Error;
+use std::fmt;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json::{self, Value};
+
+use models::*;
+
+/// Models which keep the fields they do not know about in `extra`.
+///
+/// Models are lenient: unknown fields are captured instead of being
+/// rejected, and fields Gitter may omit get default values.
+/// `from_str_strict` uses this trait to report such cases as schema drift.
+pub trait Extra {
+    /// Appends paths of the unknown fields of the model
+    /// and its nested models to `fields`
+    fn unknown_fields(&self, path: &str, fields: &mut Vec<String>);
+}
+
+impl<T: Extra> Extra for Option<T> {
+    fn unknown_fields(&self, path: &str, fields: &mut Vec<String>) {
+        if let Some(ref model) = *self {
+            model.unknown_fields(path, fields);
+        }
+    }
+}
+
+impl<T: Extra> Extra for Vec<T> {
+    fn unknown_fields(&self, path: &str, fields: &mut Vec<String>) {
+        for (i, model) in self.iter().enumerate() {
+            model.unknown_fields(&format!("{}[{}]", path, i), fields);
+        }
+    }
+}
+
+/// Joins JSON path and object key
+fn join(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", path, key)
+    }
+}
+
+macro_rules! impl_extra {
+    ($name:ident { $($field:ident => $key:expr),* }) => {
+        impl Extra for $name {
+            fn unknown_fields(&self, path: &str, fields: &mut Vec<String>) {
+                // Explicit nulls of known fields are kept in `extra` too
+                let nullable = <$name as KeepNulls>::NULLABLE_FIELDS;
+                fields.extend(
+                    self.extra
+                        .iter()
+                        .filter(|&(key, value)| !value.is_null() || !nullable.contains(&key.as_str()))
+                        .map(|(key, _)| join(path, key)),
+                );
+                $(self.$field.unknown_fields(&join(path, $key), fields);)*
+            }
+        }
+    };
+}
+
+impl_extra!(Room { permissions => "permissions" });
+impl_extra!(Permissions {});
+impl_extra!(User {});
+impl_extra!(Message {
+    from => "fromUser",
+    urls => "urls",
+    mentions => "mentions",
+    issues => "issues",
+    meta => "meta",
+    virtual_user => "virtualUser"
+});
+impl_extra!(MetaEntry {});
+impl_extra!(VirtualUser {});
+impl_extra!(Mention {});
+impl_extra!(Issue {});
+impl_extra!(Url {});
+impl_extra!(Group { backed_by => "backedBy" });
+impl_extra!(BackedBy {});
+impl_extra!(Organization { room => "room" });
+impl_extra!(Repository { room => "room" });
+impl_extra!(Channel {});
+
+impl Extra for MessageMeta {
+    fn unknown_fields(&self, path: &str, fields: &mut Vec<String>) {
+        match *self {
+            MessageMeta::Entry(ref entry) => entry.unknown_fields(path, fields),
+            MessageMeta::Other(_) => fields.push(path.to_string()),
+        }
+    }
+}
+
+impl Extra for SearchResult {
+    fn unknown_fields(&self, path: &str, fields: &mut Vec<String>) {
+        self.rooms.unknown_fields(&join(path, "results"), fields);
+    }
+}
+
+/// Differences between a Gitter response and the models of this library
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SchemaDrift {
+    /// Paths of the fields Gitter returned, which are not modeled
+    pub unknown_fields: Vec<String>,
+
+    /// Paths of the fields the models expect, which Gitter did not return
+    pub missing_fields: Vec<String>,
+}
+
+impl fmt::Display for SchemaDrift {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "schema drift:")?;
+
+        if !self.unknown_fields.is_empty() {
+            write!(f, " unknown fields [{}]", self.unknown_fields.join(", "))?;
+        }
+
+        if !self.missing_fields.is_empty() {
+            write!(f, " missing fields [{}]", self.missing_fields.join(", "))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Strict deserialization error
+#[derive(Debug)]
+pub enum SchemaError {
+    /// Response could not be deserialized at all
+    Invalid(serde_json::Error),
+
+    /// Response was deserialized, but it does not match the models
+    Drift(SchemaDrift),
+}
+
+impl fmt::Display for SchemaError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SchemaError::Invalid(ref e) => write!(f, "invalid response: {}", e),
+            SchemaError::Drift(ref drift) => drift.fmt(f),
+        }
+    }
+}
+
+impl Error for SchemaError {
+    fn description(&self) -> &str {
+        match *self {
+            SchemaError::Invalid(_) => "invalid response",
+            SchemaError::Drift(_) => "schema drift",
+        }
+    }
+}
+
+impl From<serde_json::Error> for SchemaError {
+    fn from(e: serde_json::Error) -> SchemaError {
+        SchemaError::Invalid(e)
+    }
+}
+
+/// Deserializes `json` and fails if it contains fields the models
+/// do not know about, or lacks fields the models fall back to defaults for.
+///
+/// Intended for tests, which should notice when the Gitter schema changes:
+///
+/// ```rust,ignore
+/// let room = gitter::from_str_strict::<Room>(&json).unwrap();
+/// ```
+pub fn from_str_strict<T>(json: &str) -> Result<T, SchemaError>
+where
+    T: DeserializeOwned + Serialize + Extra,
+{
+    from_value_strict(serde_json::from_str(json)?)
+}
+
+/// Same as `from_str_strict`, but takes already parsed JSON
+pub fn from_value_strict<T>(raw: Value) -> Result<T, SchemaError>
+where
+    T: DeserializeOwned + Serialize + Extra,
+{
+    let model = serde_json::from_value::<T>(raw.clone())?;
+
+    let mut unknown_fields = Vec::new();
+    model.unknown_fields("", &mut unknown_fields);
+
+    // Serialized model contains every field the model expects,
+    // including the ones filled with defaults
+    let mut missing_fields = Vec::new();
+    collect_missing_fields(&raw, &serde_json::to_value(&model)?, "", &mut missing_fields);
+
+    if unknown_fields.is_empty() && missing_fields.is_empty() {
+        Ok(model)
+    } else {
+        Err(SchemaError::Drift(SchemaDrift {
+            unknown_fields,
+            missing_fields,
+        }))
+    }
+}
+
+fn collect_missing_fields(raw: &Value, model: &Value, path: &str, fields: &mut Vec<String>) {
+    match (raw, model) {
+        (Value::Object(raw), Value::Object(model)) => for (key, value) in model {
+            let key_path = join(path, key);
+
+            match raw.get(key) {
+                Some(raw_value) => collect_missing_fields(raw_value, value, &key_path, fields),
+                None => fields.push(key_path),
+            }
+        },
+        (Value::Array(raw), Value::Array(model)) => {
+            for (i, (raw_value, value)) in raw.iter().zip(model).enumerate() {
+                collect_missing_fields(raw_value, value, &format!("{}[{}]", path, i), fields);
+            }
+        }
+        _ => {}
+    }
+}
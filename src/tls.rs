@@ -0,0 +1,118 @@
+use std::fmt;
+use reqwest::{Certificate, ClientBuilder, Identity};
+
+const PEM_END: &str = "-----END CERTIFICATE-----";
+
+/// TLS settings of the HTTP client `GitterBuilder` creates.
+///
+/// Needed behind corporate proxies which re-sign traffic with their
+/// own CA, and for internal gateways which require client certificates:
+///
+/// ```rust,ignore
+/// let tls = TlsConfig::new()
+///     .ca_bundle_pem(fs::read("/etc/ssl/corp-ca.pem")?)
+///     .client_identity_pkcs12(fs::read("bot.p12")?, "password");
+///
+/// let api = Gitter::builder(token).tls(tls).build()?;
+/// ```
+///
+/// # Certificate pinning
+///
+/// Pinning is not supported. The HTTP client exposes neither the server
+/// certificate nor a hook into its verification, and checking a fingerprint
+/// over a separate connection would not cover the connections the requests
+/// are sent over. The system roots cannot be dropped either, so trusting
+/// only a private CA needs a system trust store without public roots.
+/// Where pinning is required, send the traffic through a proxy which pins
+/// and pass a client configured for it to `Gitter::from_client`
+#[derive(Clone, Default)]
+pub struct TlsConfig {
+    root_certificates: Vec<RootCertificate>,
+    identity: Option<(Vec<u8>, String)>,
+    accept_invalid_hostnames: bool,
+}
+
+#[derive(Clone)]
+enum RootCertificate {
+    Der(Vec<u8>),
+    Pem(Vec<u8>),
+}
+
+impl fmt::Debug for TlsConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("TlsConfig")
+            .field("root_certificates", &self.root_certificates.len())
+            .field("identity", &self.identity.is_some())
+            .field("accept_invalid_hostnames", &self.accept_invalid_hostnames)
+            .finish()
+    }
+}
+
+impl TlsConfig {
+    pub fn new() -> TlsConfig {
+        TlsConfig::default()
+    }
+
+    /// Trusts a DER encoded CA certificate in addition to the system roots
+    pub fn ca_certificate_der<B>(mut self, der: B) -> TlsConfig
+        where B: Into<Vec<u8>>
+    {
+        self.root_certificates.push(RootCertificate::Der(der.into()));
+        self
+    }
+
+    /// Trusts every certificate of a PEM encoded CA bundle
+    /// in addition to the system roots
+    pub fn ca_bundle_pem<B>(mut self, pem: B) -> TlsConfig
+        where B: AsRef<[u8]>
+    {
+        let pem = String::from_utf8_lossy(pem.as_ref());
+
+        for certificate in pem.split_terminator(PEM_END).filter(|c| c.contains("-----BEGIN")) {
+            let certificate = format!("{}{}\n", certificate.trim_start(), PEM_END);
+            self.root_certificates.push(RootCertificate::Pem(certificate.into_bytes()));
+        }
+
+        self
+    }
+
+    /// Authenticates the client with a certificate and private key
+    /// from a PKCS #12 archive
+    pub fn client_identity_pkcs12<B, S>(mut self, der: B, password: S) -> TlsConfig
+        where B: Into<Vec<u8>>,
+              S: Into<String>
+    {
+        self.identity = Some((der.into(), password.into()));
+        self
+    }
+
+    /// Accepts certificates issued for other host names. Only for gateways
+    /// whose certificates cannot be fixed, it makes man-in-the-middle attacks possible
+    pub fn danger_accept_invalid_hostnames(mut self, accept: bool) -> TlsConfig {
+        self.accept_invalid_hostnames = accept;
+        self
+    }
+
+    /// Applies the settings to the HTTP client builder
+    pub(crate) fn apply(&self, builder: &mut ClientBuilder) -> Result<(), String> {
+        for certificate in &self.root_certificates {
+            let certificate = match *certificate {
+                RootCertificate::Der(ref der) => Certificate::from_der(der),
+                RootCertificate::Pem(ref pem) => Certificate::from_pem(pem),
+            };
+            builder.add_root_certificate(certificate.map_err(|e| format!("invalid CA certificate: {}", e))?);
+        }
+
+        if let Some((ref der, ref password)) = self.identity {
+            let identity = Identity::from_pkcs12_der(der, password)
+                .map_err(|e| format!("invalid client certificate: {}", e))?;
+            builder.identity(identity);
+        }
+
+        if self.accept_invalid_hostnames {
+            builder.danger_disable_hostname_verification();
+        }
+
+        Ok(())
+    }
+}
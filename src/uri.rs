@@ -0,0 +1,199 @@
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde::de;
+
+use models::*;
+
+/// Base URL of the Gitter web app
+pub const GITTER_URL: &str = "https://gitter.im";
+
+/// Error returned when a string is not a valid room URI
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseRoomUriError {
+    uri: String,
+}
+
+impl fmt::Display for ParseRoomUriError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "invalid room URI {:?}: expected org, org/repo, org/repo/channel or @username",
+            self.uri
+        )
+    }
+}
+
+impl Error for ParseRoomUriError {
+    fn description(&self) -> &str {
+        "invalid room URI"
+    }
+}
+
+/// Room URI, the path of the room on gitter.im.
+///
+/// Accepts `org`, `org/repo`, `org/repo/channel` and `@username` forms,
+/// as well as full `https://gitter.im/...` URLs. URIs are case insensitive,
+/// so they are stored in lower case:
+///
+/// ```rust,ignore
+/// let uri: RoomUri = "https://gitter.im/Rust-Lang/Rust?at=5a1b".parse()?;
+/// assert_eq!("rust-lang/rust", uri.as_str());
+/// assert_eq!(GithubType::Repo, uri.github_type());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct RoomUri {
+    uri: String,
+    user: bool,
+}
+
+impl RoomUri {
+    /// Parses room URI or gitter.im URL
+    pub fn new<S>(uri: S) -> Result<RoomUri, ParseRoomUriError>
+        where S: AsRef<str>
+    {
+        let uri = uri.as_ref();
+        let path = strip_gitter_url(uri).unwrap_or(uri);
+
+        Self::parse_path(path).ok_or_else(|| ParseRoomUriError { uri: uri.to_string() })
+    }
+
+    /// Parses gitter.im URL, such as `https://gitter.im/org/repo`
+    pub fn from_url<S>(url: S) -> Result<RoomUri, ParseRoomUriError>
+        where S: AsRef<str>
+    {
+        let url = url.as_ref();
+
+        strip_gitter_url(url)
+            .and_then(Self::parse_path)
+            .ok_or_else(|| ParseRoomUriError { uri: url.to_string() })
+    }
+
+    /// Creates URI of the one to one room with the user
+    pub fn user<S>(username: S) -> Result<RoomUri, ParseRoomUriError>
+        where S: AsRef<str>
+    {
+        let username = username.as_ref().trim_start_matches('@');
+        Self::new(format!("@{}", username))
+    }
+
+    fn parse_path(path: &str) -> Option<RoomUri> {
+        let path = path.split(&['?', '#'][..]).next().unwrap_or("");
+        let path = path.trim_matches('/');
+        let user = path.starts_with('@');
+        let path = path.trim_start_matches('@');
+
+        let segments = path.split('/').collect::<Vec<_>>();
+        let valid = !path.is_empty()
+            && segments.len() <= if user { 1 } else { 3 }
+            && segments.iter().all(|s| is_segment(s));
+
+        if valid {
+            Some(RoomUri {
+                uri: path.to_lowercase(),
+                user,
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Returns URI as Gitter expects it, without the leading `@` of users
+    pub fn as_str(&self) -> &str {
+        &self.uri
+    }
+
+    /// Returns the path segments: org, repo and channel
+    pub fn segments(&self) -> Vec<&str> {
+        self.uri.split('/').collect()
+    }
+
+    /// Determines whether the URI was given in the `@username` form
+    pub fn is_user(&self) -> bool {
+        self.user
+    }
+
+    /// Guesses the room type from the shape of the URI.
+    ///
+    /// The guess can be wrong: `org` may be a user and `org/name` may be
+    /// an organization channel, only Gitter knows for sure
+    pub fn github_type(&self) -> GithubType {
+        match (self.user, self.segments().len()) {
+            (true, _) => GithubType::OneToOne,
+            (false, 1) => GithubType::Org,
+            (false, 2) => GithubType::Repo,
+            _ => GithubType::RepoChannel,
+        }
+    }
+
+    /// Returns full gitter.im URL of the room
+    pub fn to_url(&self) -> String {
+        format!("{}/{}", GITTER_URL, self.uri)
+    }
+
+    /// Determines whether the URI returned by Gitter points to this room
+    pub fn matches<S>(&self, uri: S) -> bool
+        where S: AsRef<str>
+    {
+        uri.as_ref().trim_matches('/').eq_ignore_ascii_case(&self.uri)
+    }
+}
+
+/// Returns path of gitter.im URL, `None` if `url` is not one
+fn strip_gitter_url(url: &str) -> Option<&str> {
+    let rest = url.trim_start_matches("https://").trim_start_matches("http://");
+    let rest = rest.trim_start_matches("www.");
+    let host_len = rest.find('/').unwrap_or(rest.len());
+
+    if rest[..host_len].eq_ignore_ascii_case("gitter.im") {
+        Some(&rest[host_len..])
+    } else {
+        None
+    }
+}
+
+/// GitHub names consist of letters, digits, `-`, `_` and `.`
+fn is_segment(segment: &str) -> bool {
+    !segment.is_empty()
+        && segment != "."
+        && segment != ".."
+        && segment.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+}
+
+impl FromStr for RoomUri {
+    type Err = ParseRoomUriError;
+
+    fn from_str(s: &str) -> Result<RoomUri, ParseRoomUriError> {
+        RoomUri::new(s)
+    }
+}
+
+impl fmt::Display for RoomUri {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.uri)
+    }
+}
+
+impl AsRef<str> for RoomUri {
+    fn as_ref(&self) -> &str {
+        &self.uri
+    }
+}
+
+impl Serialize for RoomUri {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        serializer.serialize_str(&self.uri)
+    }
+}
+
+impl<'de> Deserialize<'de> for RoomUri {
+    fn deserialize<D>(deserializer: D) -> Result<RoomUri, D::Error>
+        where D: Deserializer<'de>
+    {
+        let uri = String::deserialize(deserializer)?;
+        RoomUri::new(uri).map_err(de::Error::custom)
+    }
+}
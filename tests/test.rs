@@ -1,15 +1,95 @@
+extern crate chrono;
 extern crate gitter;
+extern crate reqwest;
 extern crate serde;
 extern crate serde_json;
 
 use gitter::*;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::fmt::Debug;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{channel, Receiver};
+use std::thread;
+use std::time::Duration;
 
-fn get_gitter_api<'a>() -> Gitter<'a> {
+fn get_gitter_api() -> Gitter {
     let token = std::env::var("GITTER_BOT_TOKEN")
         .unwrap_or_else(|_| "GITTER_TOKEN_VALUE".into());
     Gitter::new(token).unwrap()
 }
 
+/// Reads a whole HTTP request (head and body) from the stream
+fn read_request<R: Read>(stream: &mut R) -> String {
+    let mut request = Vec::new();
+    let mut buf = [0; 4096];
+
+    loop {
+        let head_len = request.windows(4).position(|w| w == b"\r\n\r\n").map(|p| p + 4);
+
+        if let Some(head_len) = head_len {
+            let head = String::from_utf8_lossy(&request[..head_len]).to_lowercase();
+            let body_len = head
+                .lines()
+                .find(|l| l.starts_with("content-length:"))
+                .and_then(|l| l["content-length:".len()..].trim().parse::<usize>().ok())
+                .unwrap_or(0);
+
+            if request.len() >= head_len + body_len {
+                break;
+            }
+        }
+
+        let n = stream.read(&mut buf).unwrap();
+        if n == 0 {
+            break;
+        }
+        request.extend_from_slice(&buf[..n]);
+    }
+
+    String::from_utf8_lossy(&request).into_owned()
+}
+
+/// Starts a local server which answers requests with `responses`: status, extra headers
+/// and body, one by one. Returns the base url to pass to `Gitter::with_api_base_url`
+/// and the received requests
+fn serve_with_headers(responses: Vec<(&'static str, &'static str, &'static str)>) -> (String, Receiver<String>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let base_url = format!("http://{}", listener.local_addr().unwrap());
+    let (requests, received) = channel();
+
+    thread::spawn(move || {
+        for (status, headers, body) in responses {
+            let (mut stream, _) = listener.accept().unwrap();
+
+            requests.send(read_request(&mut stream)).unwrap();
+
+            let response = format!(
+                "HTTP/1.1 {}\r\n{}Content-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                status,
+                headers,
+                body.len(),
+                body
+            );
+            stream.write_all(response.as_bytes()).unwrap();
+        }
+    });
+
+    (base_url, received)
+}
+
+/// Starts a local server which answers requests with `responses`, one by one
+fn serve(responses: Vec<(&'static str, &'static str)>) -> String {
+    serve_with_headers(responses.into_iter().map(|(status, body)| (status, "", body)).collect()).0
+}
+
+/// Starts a local server which answers a single request with `status` and `body`
+fn serve_once(status: &'static str, body: &'static str) -> String {
+    serve(vec![(status, body)])
+}
+
 #[test]
 fn deserialize_user() {
     let user_json_str = "[{
@@ -23,13 +103,1268 @@ fn deserialize_user() {
 
     let user = serde_json::from_str::<Vec<User>>(user_json_str).unwrap();
 
-    assert_eq!("53307734c3599d1de448e192", user[0].id);
+    assert_eq!("53307734c3599d1de448e192", user[0].id.as_str());
+}
+
+/// Deserializes `json` into `T`, serializes it back
+/// and checks that nothing was lost or added on the way
+fn assert_round_trip<T>(json: &str)
+where
+    T: Serialize + DeserializeOwned + PartialEq + Debug,
+{
+    let model = serde_json::from_str::<T>(json).unwrap();
+    let model_json = serde_json::to_string(&model).unwrap();
+
+    let expected = serde_json::from_str::<serde_json::Value>(json).unwrap();
+    let actual = serde_json::from_str::<serde_json::Value>(&model_json).unwrap();
+    assert_eq!(expected, actual);
+
+    assert_eq!(model, serde_json::from_str::<T>(&model_json).unwrap());
+}
+
+#[test]
+fn round_trip_user() {
+    assert_round_trip::<User>(r#"{
+        "id": "53307734c3599d1de448e192",
+        "username": "malditogeek",
+        "displayName": "Mauro Pompilio",
+        "url": "/malditogeek",
+        "avatarUrlSmall": "https://avatars.githubusercontent.com/u/14751?",
+        "avatarUrlMedium": "https://avatars.githubusercontent.com/u/14751?",
+        "v": 3
+    }"#);
+}
+
+#[test]
+fn round_trip_room() {
+    assert_round_trip::<Room>(r#"{
+        "id": "5330777dc3599d1de448e194",
+        "name": "gitterHQ",
+        "topic": "Gitter",
+        "uri": "gitterHQ",
+        "oneToOne": false,
+        "userCount": 2,
+        "unreadItems": 0,
+        "mentions": 0,
+        "lastAccessTime": "2014-03-24T18:22:28.105Z",
+        "lurk": false,
+        "url": "/gitterHQ",
+        "githubType": "ORG",
+        "tags": ["rust", "api"],
+        "roomMember": true,
+        "groupId": "57542c12c43b8c601976fa66",
+        "permissions": { "admin": true },
+        "v": 1
+    }"#);
+}
+
+#[test]
+fn round_trip_message() {
+    assert_round_trip::<Message>(r#"{
+        "id": "53316dc47bfc1a000000000f",
+        "text": "Hi @suprememoocow !",
+        "html": "Hi <span data-link-type=\"mention\" data-screen-name=\"suprememoocow\" class=\"mention\">@suprememoocow</span> !",
+        "sent": "2014-03-25T11:51:32.289Z",
+        "editedAt": "2014-03-25T11:52:01.000Z",
+        "fromUser": {
+            "id": "53307734c3599d1de448e192",
+            "username": "malditogeek",
+            "displayName": "Mauro Pompilio",
+            "url": "/malditogeek",
+            "avatarUrlSmall": "https://avatars.githubusercontent.com/u/14751?",
+            "avatarUrlMedium": "https://avatars.githubusercontent.com/u/14751?"
+        },
+        "unread": false,
+        "readBy": 0,
+        "urls": [{ "url": "https://github.com/shmutalov/gitter-rs" }],
+        "mentions": [{
+            "screenName": "suprememoocow",
+            "userId": "53307831c3599d1de448e19a"
+        }],
+        "issues": [{ "number": "4" }],
+        "v": 1
+    }"#);
+}
+
+#[test]
+fn round_trip_group() {
+    assert_round_trip::<Group>(r#"{
+        "id": "57542c12c43b8c601976fa66",
+        "name": "gitterHQ",
+        "uri": "gitterHQ",
+        "backedBy": {
+            "type": "GH_ORG",
+            "linkPath": "gitterHQ"
+        },
+        "avatarUrl": "https://avatars-02.gitter.im/group/iv/3/57542c12c43b8c601976fa66"
+    }"#);
+}
+
+#[test]
+fn round_trip_organization() {
+    assert_round_trip::<Organization>(r#"{
+        "id": 5990364,
+        "name": "gitterHQ",
+        "avatarUrl": "https://avatars.githubusercontent.com/u/5990364?"
+    }"#);
+}
+
+#[test]
+fn round_trip_repository() {
+    assert_round_trip::<Repository>(r#"{
+        "id": 117416196,
+        "name": "shmutalov/gitter-rs",
+        "uri": "shmutalov/gitter-rs",
+        "private": false,
+        "room": {
+            "id": "5a6b1b9cd73408ce4f8a0c5e",
+            "name": "shmutalov/gitter-rs",
+            "topic": "",
+            "uri": "shmutalov/gitter-rs",
+            "oneToOne": false,
+            "userCount": 1,
+            "unreadItems": 0,
+            "mentions": 0,
+            "lurk": false,
+            "url": "/shmutalov/gitter-rs",
+            "githubType": "REPO",
+            "roomMember": true
+        }
+    }"#);
+}
+
+#[test]
+fn round_trip_channel() {
+    assert_round_trip::<Channel>(r#"{
+        "id": "5a6c5d2ad73408ce4f8a50c5",
+        "name": "gitter-rs/testing",
+        "topic": "Testing room",
+        "uri": "gitter-rs/testing",
+        "oneToOne": false,
+        "unreadItems": 0,
+        "mentions": 0,
+        "lastAccessTime": "2018-01-27T11:13:46.000Z",
+        "lurk": false,
+        "url": "/gitter-rs/testing",
+        "githubType": "ORG_CHANNEL",
+        "security": "PUBLIC"
+    }"#);
+}
+
+#[test]
+fn round_trip_keeps_nulls() {
+    let json = include_str!("fixtures/room_with_nulls.json");
+    let room = serde_json::from_str::<Room>(json).unwrap();
+
+    assert_eq!(None, room.uri);
+    assert_eq!(None, room.last_access_time);
+    assert_ne!(room, serde_json::from_str::<Room>(r#"{"id":"5330777dc3599d1de448e194","name":"gitterHQ","url":"/gitterHQ","githubType":"ORG"}"#).unwrap());
+
+    // Explicit nulls are written back after the other fields, and are no schema drift
+    assert_eq!(json.trim_end(), serde_json::to_string_pretty(&room).unwrap());
+    assert_eq!(room, from_str_strict::<Room>(json).unwrap());
+
+    // A field which got a value is written once
+    let mut room = room;
+    room.uri = Some("gitterHQ".to_string());
+    let room_json = serde_json::to_string(&room).unwrap();
+    assert_eq!(1, room_json.matches(r#""uri""#).count());
+    assert!(room_json.contains(r#""uri":"gitterHQ""#));
+}
+
+#[test]
+fn parse_ids() {
+    let room_id = "5330777dc3599d1de448e194".parse::<RoomId>().unwrap();
+
+    assert_eq!("5330777dc3599d1de448e194", room_id.to_string());
+    assert_eq!("2014-03-24T18:20:45Z", room_id.timestamp().to_rfc3339_opts(chrono::SecondsFormat::Secs, true));
+
+    assert!(UserId::new("5330777DC3599D1DE448E194").is_ok());
+    assert!(MessageId::new("gitter-rs/testing").is_err());
+    assert!(GroupId::new("5330777dc3599d1de448e19").is_err());
+    assert!(serde_json::from_str::<RoomId>("\"not-an-object-id\"").is_err());
+}
+
+#[test]
+fn parse_room_uris() {
+    let uri = "https://gitter.im/Gitter-RS/Testing?at=5330777dc3599d1de448e194".parse::<RoomUri>().unwrap();
+    assert_eq!("gitter-rs/testing", uri.as_str());
+    assert_eq!(vec!["gitter-rs", "testing"], uri.segments());
+    assert_eq!(GithubType::Repo, uri.github_type());
+    assert_eq!("https://gitter.im/gitter-rs/testing", uri.to_url());
+    assert!(uri.matches("gitter-RS/testing"));
+    assert_eq!(uri, RoomUri::new("gitter-rs/testing/").unwrap());
+
+    assert_eq!(GithubType::Org, RoomUri::new("gitterHQ").unwrap().github_type());
+    assert_eq!(GithubType::RepoChannel, RoomUri::new("gitterHQ/gitter/dev").unwrap().github_type());
+
+    let user = RoomUri::new("@Shmutalov").unwrap();
+    assert!(user.is_user());
+    assert_eq!(GithubType::OneToOne, user.github_type());
+    assert_eq!("https://gitter.im/shmutalov", user.to_url());
+    assert_eq!(user, RoomUri::user("shmutalov").unwrap());
+
+    assert!(RoomUri::from_url("gitter.im/gitterHQ/gitter").is_ok());
+    assert!(RoomUri::from_url("https://github.com/gitterHQ/gitter").is_err());
+    assert!(RoomUri::new("").is_err());
+    assert!(RoomUri::new("a/b/c/d").is_err());
+    assert!(RoomUri::new("@user/repo").is_err());
+    assert!(RoomUri::new("org/re po").is_err());
+    assert!(RoomUri::new("https://gitter.im/").is_err());
+
+    let join = serde_json::to_string(&JoinRoom::from_uri(&uri)).unwrap();
+    assert_eq!(r#"{"uri":"gitter-rs/testing"}"#, join);
+}
+
+#[test]
+fn deserialize_unknown_enum_values() {
+    let backed_by = serde_json::from_str::<BackedBy>(r#"{ "type": "GL_GROUP", "linkPath": "gitlab-org" }"#).unwrap();
+    assert_eq!(Some(GroupType::Unknown("GL_GROUP".to_owned())), backed_by.group_type);
+
+    let security = serde_json::from_str::<Security>(r#""SECRET""#).unwrap();
+    assert_eq!(Security::Unknown("SECRET".to_owned()), security);
+    assert_eq!(r#""SECRET""#, serde_json::to_string(&security).unwrap());
+
+    assert_eq!(GithubType::OneToOne, serde_json::from_str::<GithubType>(r#""ONETOONE""#).unwrap());
+}
+
+#[test]
+fn get_rooms_with_unknown_github_type() {
+    let base_url = serve_once("200 OK", r#"[{
+        "id": "5330777dc3599d1de448e194",
+        "name": "gitterHQ/forum",
+        "topic": "",
+        "uri": "gitterHQ/forum",
+        "oneToOne": false,
+        "userCount": 2,
+        "unreadItems": 0,
+        "mentions": 0,
+        "lurk": false,
+        "url": "/gitterHQ/forum",
+        "githubType": "FORUM",
+        "roomMember": true
+    }]"#);
+    let api = Gitter::new("token").unwrap().with_api_base_url(base_url);
+
+    let rooms = api.get_rooms().unwrap();
+
+    assert_eq!(1, rooms.len());
+    assert_eq!(GithubType::Unknown("FORUM".to_owned()), rooms[0].github_type);
+}
+
+#[test]
+fn get_room_id_from_user_rooms() {
+    let base_url = serve_once("200 OK", include_str!("fixtures/user_rooms.json"));
+    let api = Gitter::new("token").unwrap().with_api_base_url(base_url);
+    let uri = "https://gitter.im/GitterHQ/Forum".parse::<RoomUri>().unwrap();
+
+    assert_eq!("5330777dc3599d1de448e194", api.get_room_id(&uri).unwrap().as_str());
+
+    // The server is gone, the second lookup is answered from the cache
+    assert_eq!("5330777dc3599d1de448e194", api.get_room_id(&uri).unwrap().as_str());
+}
+
+#[test]
+fn get_room_id_ambiguous() {
+    let base_url = serve_once("200 OK", include_str!("fixtures/user_rooms.json"));
+    let api = Gitter::new("token").unwrap().with_api_base_url(base_url);
+
+    match api.get_room_id(&"gitterHQ".parse().unwrap()) {
+        Err(ApiError::AmbiguousRoom(room_ids)) => assert_eq!(2, room_ids.len()),
+        other => panic!("expected ambiguous room, got {:?}", other),
+    }
+}
+
+#[test]
+fn get_room_id_by_exact_uri() {
+    let (base_url, requests) = serve_with_headers(vec![
+        // Neither in the user's rooms nor searchable, the exact URI finds it
+        ("200 OK", "", "[]"),
+        ("200 OK", "", r#"{
+            "id": "5330777dc3599d1de448e194",
+            "name": "gitterHQ/unlisted",
+            "uri": "gitterHQ/unlisted",
+            "url": "/gitterHQ/unlisted",
+            "githubType": "REPO_CHANNEL"
+        }"#),
+        ("200 OK", "", "[]"),
+        ("404 Not Found", "", r#"{"error":"Not Found"}"#),
+        ("200 OK", "", "[]"),
+        ("403 Forbidden", "", r#"{"error":"Forbidden"}"#),
+    ]);
+    let api = Gitter::new("token").unwrap().with_api_base_url(base_url);
+
+    let room_id = api.get_room_id(&"gitterHQ/unlisted".parse().unwrap()).unwrap();
+    assert_eq!("5330777dc3599d1de448e194", room_id.as_str());
+
+    match api.get_room_id(&"gitterHQ/missing".parse().unwrap()) {
+        Err(ApiError::RoomNotFound) => {}
+        other => panic!("expected room not found, got {:?}", other),
+    }
+
+    match api.get_room_id(&"gitterHQ/private".parse().unwrap()) {
+        Err(ApiError::PermissionDenied(_)) => {}
+        other => panic!("expected permission denied, got {:?}", other),
+    }
+
+    // The search is never asked
+    for uri in &["gitterHQ/unlisted", "gitterHQ/missing", "gitterHQ/private"] {
+        assert!(requests.recv().unwrap().starts_with("GET /rooms "));
+        let lookup = requests.recv().unwrap();
+        assert!(lookup.starts_with("POST /rooms "), "{}", lookup);
+        assert!(lookup.ends_with(&format!(r#"{{"uri":"{}"}}"#, uri)), "{}", lookup);
+    }
+}
+
+#[test]
+fn room_permissions_from_descriptor() {
+    let room = r#"{
+        "id": "5330777dc3599d1de448e194",
+        "name": "shmutalov/gitter-rs",
+        "url": "/shmutalov/gitter-rs",
+        "githubType": "REPO",
+        "roomMember": true,
+        "groupId": "57542c12c43b8c601976fa66"
+    }"#;
+    let org_group = r#"{
+        "id": "57542c12c43b8c601976fa66",
+        "name": "gitterHQ",
+        "uri": "gitterHQ",
+        "backedBy": { "type": "GH_ORG", "linkPath": "gitterHQ" },
+        "avatarUrl": "https://avatars-02.gitter.im/group/iv/3/57542c12c43b8c601976fa66"
+    }"#;
+    let base_url = serve(vec![
+        // Gitter left out the permissions, the group is backed by the current user's account
+        ("200 OK", room),
+        ("200 OK", r#"{
+            "id": "57542c12c43b8c601976fa66",
+            "name": "shmutalov",
+            "uri": "shmutalov",
+            "backedBy": { "type": "GH_USER", "linkPath": "shmutalov" },
+            "avatarUrl": "https://avatars-02.gitter.im/group/iv/3/57542c12c43b8c601976fa66"
+        }"#),
+        ("200 OK", r#"[{
+            "id": "53307734c3599d1de448e192",
+            "username": "shmutalov",
+            "displayName": "Sadykh Mutalov",
+            "url": "/shmutalov"
+        }]"#),
+        // Admins of an organization are not visible
+        ("200 OK", room),
+        ("200 OK", org_group),
+        // The group cannot be read
+        ("200 OK", room),
+        ("404 Not Found", r#"{"error":"Not Found"}"#),
+        // Gitter reports the current user is no admin, nothing is removed
+        ("200 OK", r#"{
+            "id": "5330777dc3599d1de448e194",
+            "name": "shmutalov/gitter-rs",
+            "roomMember": true,
+            "permissions": { "admin": false }
+        }"#),
+        // Unknown rights, the server refuses
+        ("200 OK", room),
+        ("200 OK", org_group),
+        ("403 Forbidden", r#"{"error":"Forbidden"}"#),
+    ]);
+    let api = Gitter::new("token").unwrap().with_api_base_url(base_url);
+    let room_id = RoomId::new("5330777dc3599d1de448e194").unwrap();
+
+    let permissions = api.get_room_permissions(&room_id).unwrap();
+    assert_eq!(Some(RoomRole::Admin), permissions.role);
+    assert_eq!(Some(GroupType::GhUser), permissions.backed_by.unwrap().group_type);
+
+    let permissions = api.get_room_permissions(&room_id).unwrap();
+    assert_eq!(None, permissions.is_admin());
+    assert!(permissions.member);
+    assert_eq!(Some(GroupType::GhOrg), permissions.backed_by.unwrap().group_type);
+
+    match api.get_room_permissions(&room_id) {
+        Err(ApiError::NotFound(_)) => {}
+        other => panic!("expected the group error, got {:?}", other),
+    }
+
+    let user_id = UserId::new("53307734c3599d1de448e192").unwrap();
+    for _ in 0..2 {
+        match api.remove_user_from_room(&room_id, &user_id) {
+            Err(ApiError::PermissionDenied(_)) => {}
+            other => panic!("expected permission denied, got {:?}", other),
+        }
+    }
+}
+
+#[test]
+fn endpoint_response_shapes() {
+    let base_url = serve(vec![
+        // leave_room
+        ("204 No Content", ""),
+        ("200 OK", r#"{"success":true}"#),
+        ("200 OK", "not json"),
+        // delete_room
+        ("200 OK", r#"{"success":true}"#),
+        ("500 Internal Server Error", r#"{"error":"Server error"}"#),
+        // update_message returns the updated message, which is not needed
+        ("200 OK", include_str!("fixtures/message_status.json")),
+        ("204 No Content", ""),
+        // mark_messages_as_read
+        ("200 OK", r#"{"success":true}"#),
+        // get_room and send_message
+        ("200 OK", ""),
+        ("200 OK", "  "),
+    ]);
+    let api = Gitter::new("token")
+        .unwrap()
+        .with_api_base_url(base_url)
+        .with_retry_policy(RetryPolicy::none());
+    let room_id = RoomId::new("5330777dc3599d1de448e194").unwrap();
+    let user_id = UserId::new("53307734c3599d1de448e192").unwrap();
+    let message_id = MessageId::new("53316dc47bfc1a000000000f").unwrap();
+
+    api.leave_room(&room_id, &user_id).unwrap();
+    api.leave_room(&room_id, &user_id).unwrap();
+    match api.leave_room(&room_id, &user_id) {
+        Err(ApiError::BadResponse(_)) => {}
+        other => panic!("expected bad response, got {:?}", other),
+    }
+
+    api.delete_room(&room_id).unwrap();
+    match api.delete_room(&room_id) {
+        Err(ApiError::BadResponse(ref message)) if message.contains("500") => {}
+        other => panic!("expected bad response, got {:?}", other),
+    }
+
+    api.update_message(&room_id, &message_id, "edited").unwrap();
+    api.update_message(&room_id, &message_id, "edited").unwrap();
+
+    api.mark_messages_as_read(&user_id, &room_id, &[message_id]).unwrap();
+
+    match api.get_room(&room_id) {
+        Err(ApiError::EmptyResponse) => {}
+        other => panic!("expected empty response, got {:?}", other),
+    }
+    match api.send_message(&room_id, "hello") {
+        Err(ApiError::EmptyResponse) => {}
+        other => panic!("expected empty response, got {:?}", other),
+    }
+}
+
+#[test]
+fn cache_get_responses() {
+    let (base_url, _) = serve_with_headers(vec![
+        ("200 OK", "", include_str!("fixtures/user_rooms.json")),
+        ("403 Forbidden", "", r#"{"error":"Forbidden"}"#),
+        ("200 OK", "", r#"{
+            "id": "5330777dc3599d1de448e194",
+            "name": "gitterHQ",
+            "topic": "topic",
+            "uri": "gitterHQ",
+            "url": "/gitterHQ",
+            "githubType": "ORG"
+        }"#),
+        ("200 OK", "", "[]"),
+    ]);
+    let api = Gitter::new("token")
+        .unwrap()
+        .with_api_base_url(base_url)
+        .with_cache(Duration::from_secs(60));
+
+    assert_eq!(3, api.get_rooms().unwrap().len());
+    assert_eq!(3, api.get_rooms().unwrap().len());
+
+    // A failed update changes nothing, the room list stays cached
+    let room_id = RoomId::new("5330777dc3599d1de448e194").unwrap();
+    assert!(api.update_room_topic(&room_id, "topic").is_err());
+    assert_eq!(3, api.get_rooms().unwrap().len());
+
+    // A successful update drops the cached room list
+    assert_eq!("topic", api.update_room_topic(&room_id, "topic").unwrap().topic);
+    assert_eq!(0, api.get_rooms().unwrap().len());
+
+    let stats = api.cache_stats().unwrap();
+    assert_eq!(2, stats.hits);
+    assert_eq!(2, stats.misses);
+    assert_eq!(1, stats.invalidations);
+}
+
+#[test]
+fn cache_revalidates_stale_responses() {
+    let (base_url, requests) = serve_with_headers(vec![
+        ("200 OK", "ETag: \"v1\"\r\n", include_str!("fixtures/user_rooms.json")),
+        ("304 Not Modified", "", ""),
+    ]);
+    let api = Gitter::new("token")
+        .unwrap()
+        .with_api_base_url(base_url)
+        .with_cache(Duration::from_secs(0));
+
+    assert_eq!(3, api.get_rooms().unwrap().len());
+    assert_eq!(3, api.get_rooms().unwrap().len());
+
+    requests.recv().unwrap();
+    assert!(requests.recv().unwrap().to_lowercase().contains("if-none-match: \"v1\""));
+
+    let stats = api.cache_stats().unwrap();
+    assert_eq!(0, stats.hits);
+    assert_eq!(1, stats.revalidations);
+    assert_eq!(1, stats.misses);
+}
+
+#[test]
+fn cache_refetches_invalidated_revalidation() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let base_url = format!("http://{}", listener.local_addr().unwrap());
+    let (requests, received) = channel();
+    let (invalidated, wait_invalidated) = channel();
+
+    thread::spawn(move || {
+        let respond = |stream: &mut TcpStream, status: &str, headers: &str, body: &str| {
+            let response = format!(
+                "HTTP/1.1 {}\r\n{}Content-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                status,
+                headers,
+                body.len(),
+                body
+            );
+            stream.write_all(response.as_bytes()).unwrap();
+        };
+
+        let (mut stream, _) = listener.accept().unwrap();
+        requests.send(read_request(&mut stream)).unwrap();
+        respond(&mut stream, "200 OK", "ETag: \"v1\"\r\n", include_str!("fixtures/user_rooms.json"));
+
+        // Hold the conditional request until the room list was invalidated
+        let (mut conditional, _) = listener.accept().unwrap();
+        requests.send(read_request(&mut conditional)).unwrap();
+
+        let (mut stream, _) = listener.accept().unwrap();
+        requests.send(read_request(&mut stream)).unwrap();
+        respond(&mut stream, "200 OK", "", r#"{
+            "id": "5330777dc3599d1de448e194",
+            "name": "gitterHQ",
+            "uri": "gitterHQ",
+            "url": "/gitterHQ",
+            "githubType": "ORG"
+        }"#);
+
+        wait_invalidated.recv().unwrap();
+        respond(&mut conditional, "304 Not Modified", "", "");
+
+        let (mut stream, _) = listener.accept().unwrap();
+        requests.send(read_request(&mut stream)).unwrap();
+        respond(&mut stream, "200 OK", "", "[]");
+    });
+
+    let api = Gitter::new("token")
+        .unwrap()
+        .with_api_base_url(base_url)
+        .with_cache(Duration::from_secs(0));
+
+    assert_eq!(3, api.get_rooms().unwrap().len());
+    received.recv().unwrap();
+
+    let reader = {
+        let api = api.clone();
+        thread::spawn(move || api.get_rooms().unwrap().len())
+    };
+    assert!(received.recv().unwrap().to_lowercase().contains("if-none-match: \"v1\""));
+
+    api.join_room_by_uri(&"gitterHQ".parse().unwrap()).unwrap();
+    assert!(received.recv().unwrap().starts_with("POST /rooms "));
+    invalidated.send(()).unwrap();
+
+    // The 304 refers to the dropped entry, so the list is fetched again
+    assert_eq!(0, reader.join().unwrap());
+    let refetch = received.recv().unwrap().to_lowercase();
+    assert!(refetch.starts_with("get /rooms ") && !refetch.contains("if-none-match"));
+
+    let stats = api.cache_stats().unwrap();
+    assert_eq!(0, stats.revalidations);
+    assert_eq!(2, stats.misses);
+}
+
+#[test]
+fn retry_policy_delays() {
+    let policy = RetryPolicy::new()
+        .base_delay(Duration::from_millis(100))
+        .max_delay(Duration::from_millis(300))
+        .jitter(false);
+
+    assert_eq!(Duration::from_millis(100), policy.delay(1, None));
+    assert_eq!(Duration::from_millis(200), policy.delay(2, None));
+    assert_eq!(Duration::from_millis(300), policy.delay(3, None));
+    assert_eq!(Duration::from_millis(300), policy.delay(1, Some(Duration::from_secs(5))));
+
+    let jittered = RetryPolicy::new().base_delay(Duration::from_millis(100)).delay(1, None);
+    assert!(jittered >= Duration::from_millis(50) && jittered <= Duration::from_millis(100));
+
+    assert!(RetryPolicy::new().is_retryable_status(503));
+    assert!(!RetryPolicy::new().is_retryable_status(400));
+    assert_eq!(1, RetryPolicy::none().attempts());
+
+    assert!(RetryPolicy::new().is_retryable_method(HttpMethod::Delete));
+    assert!(!RetryPolicy::new().is_retryable_method(HttpMethod::Post));
+    let posts = RetryPolicy::new().retry_methods(vec![HttpMethod::Post]);
+    assert!(posts.is_retryable_method(HttpMethod::Post));
+    assert!(!posts.is_retryable_method(HttpMethod::Get));
+}
+
+#[test]
+fn retry_get_requests() {
+    let base_url = serve(vec![
+        ("503 Service Unavailable", "{}"),
+        ("200 OK", include_str!("fixtures/user_rooms.json")),
+    ]);
+    let api = Gitter::new("token")
+        .unwrap()
+        .with_api_base_url(base_url)
+        .with_retry_policy(RetryPolicy::new().base_delay(Duration::from_millis(1)));
+
+    assert_eq!(3, api.get_rooms().unwrap().len());
+}
+
+#[test]
+fn retry_post_requests_only_with_idempotency_key() {
+    let (base_url, requests) = serve_with_headers(vec![
+        ("503 Service Unavailable", "", "{}"),
+        ("503 Service Unavailable", "", "{}"),
+        ("200 OK", "", include_str!("fixtures/message_status.json")),
+    ]);
+    let api = Gitter::new("token")
+        .unwrap()
+        .with_api_base_url(base_url)
+        .with_retry_policy(RetryPolicy::new().base_delay(Duration::from_millis(1)));
+    let room_id = RoomId::new("5330777dc3599d1de448e194").unwrap();
+
+    assert!(api.send_message(&room_id, "hello").is_err());
+    assert!(api.send_message_with_idempotency_key(&room_id, "hello", "hello-1").is_ok());
+
+    requests.recv().unwrap();
+    assert!(requests.recv().unwrap().to_lowercase().contains("idempotency-key: hello-1"));
+}
+
+#[test]
+fn retry_configured_methods() {
+    let base_url = serve(vec![
+        ("503 Service Unavailable", "{}"),
+        ("200 OK", include_str!("fixtures/message_status.json")),
+        ("503 Service Unavailable", "{}"),
+    ]);
+    let policy = RetryPolicy::new()
+        .base_delay(Duration::from_millis(1))
+        .retry_methods(vec![HttpMethod::Post]);
+    let api = Gitter::new("token")
+        .unwrap()
+        .with_api_base_url(base_url)
+        .with_retry_policy(policy);
+    let room_id = RoomId::new("5330777dc3599d1de448e194").unwrap();
+
+    assert!(api.send_message(&room_id, "hello").is_ok());
+    assert!(api.get_rooms().is_err());
+}
+
+#[test]
+fn retry_delete_after_lost_response() {
+    let base_url = serve(vec![
+        ("503 Service Unavailable", "{}"),
+        ("404 Not Found", r#"{"error":"Not Found"}"#),
+        ("404 Not Found", r#"{"error":"Not Found"}"#),
+    ]);
+    let api = Gitter::new("token")
+        .unwrap()
+        .with_api_base_url(base_url)
+        .with_retry_policy(RetryPolicy::new().base_delay(Duration::from_millis(1)));
+    let room_id = RoomId::new("5330777dc3599d1de448e194").unwrap();
+
+    // The first attempt may have deleted the room
+    assert!(api.delete_room(&room_id).is_ok());
+
+    // Without a retry the room never existed
+    match api.delete_room(&room_id) {
+        Err(ApiError::NotFound(_)) => {}
+        other => panic!("expected not found, got {:?}", other),
+    }
+}
+
+#[test]
+fn send_limits_fail_over_limit() {
+    let base_url = serve(vec![
+        ("200 OK", include_str!("fixtures/message_status.json")),
+        ("200 OK", include_str!("fixtures/message_status.json")),
+    ]);
+    let limits = SendLimits::new()
+        .global(RateLimit::new(2, Duration::from_secs(60)))
+        .over_limit(OverLimit::Fail);
+    let api = Gitter::new("token").unwrap().with_api_base_url(base_url).with_send_limits(limits);
+    let room_id = RoomId::new("5330777dc3599d1de448e194").unwrap();
+    let other_room_id = RoomId::new("52b42a52ed5ab0b3bf051b93").unwrap();
+
+    api.send_message(&room_id, "first").unwrap();
+    api.send_message(&other_room_id, "second").unwrap();
+
+    match api.send_message(&room_id, "third") {
+        Err(ApiError::RateLimited(wait)) => assert!(wait > Duration::from_secs(20) && wait <= Duration::from_secs(30)),
+        other => panic!("expected rate limited, got {:?}", other),
+    }
+}
+
+#[test]
+fn send_limits_queue_in_order() {
+    let (base_url, requests) = serve_with_headers(vec![
+        ("200 OK", "", include_str!("fixtures/message_status.json")),
+        ("200 OK", "", include_str!("fixtures/message_status.json")),
+        ("200 OK", "", include_str!("fixtures/message_status.json")),
+    ]);
+    let limits = SendLimits::new().per_room(RateLimit::new(1, Duration::from_millis(100)));
+    let api = Gitter::new("token").unwrap().with_api_base_url(base_url).with_send_limits(limits);
+    let room_id = RoomId::new("5330777dc3599d1de448e194").unwrap();
+    let started_at = std::time::Instant::now();
+
+    let senders = ["first", "second", "third"]
+        .iter()
+        .map(|&text| {
+            let (api, room_id) = (api.clone(), room_id.clone());
+            let sender = thread::spawn(move || api.send_message(&room_id, text).unwrap());
+            thread::sleep(Duration::from_millis(20));
+            sender
+        })
+        .collect::<Vec<_>>();
+    for sender in senders {
+        sender.join().unwrap();
+    }
+
+    assert!(started_at.elapsed() >= Duration::from_millis(200));
+    for text in &["first", "second", "third"] {
+        assert!(requests.recv().unwrap().contains(&format!(r#""text":"{}""#, text)));
+    }
+}
+
+#[test]
+fn send_limits_with_huge_burst() {
+    let base_url = serve(vec![
+        ("200 OK", include_str!("fixtures/message_status.json")),
+        ("200 OK", include_str!("fixtures/message_status.json")),
+    ]);
+    let limit = RateLimit::new(1, Duration::from_secs(5_000_000_000)).burst(u32::max_value());
+    let api = Gitter::new("token")
+        .unwrap()
+        .with_api_base_url(base_url)
+        .with_send_limits(SendLimits::new().per_room(limit).over_limit(OverLimit::Fail));
+    let room_id = RoomId::new("5330777dc3599d1de448e194").unwrap();
+
+    api.send_message(&room_id, "first").unwrap();
+    api.send_message(&room_id, "second").unwrap();
+}
+
+#[test]
+fn outbox_delivers_in_order_and_survives_restart() {
+    // The first attempt of "first" was posted although its response was lost
+    let user = r#"{"id":"5a6b1a5ed73408ce4f8a0b2f","username":"shmutalov","displayName":"Sherzod Mutalov","url":"/shmutalov"}"#;
+    let posted = format!(
+        r#"[{{"id":"5f1e9c0e3c1a4e2b6c8d0f5b","text":"first","sent":"{}","fromUser":{}}}]"#,
+        chrono::Utc::now().to_rfc3339(),
+        user
+    );
+    let (base_url, requests) = serve_with_headers(vec![
+        ("503 Service Unavailable", "", "{}"),
+        ("200 OK", "", include_str!("fixtures/message_status.json")),
+        ("200 OK", "", Box::leak(format!("[{}]", user).into_boxed_str())),
+        ("200 OK", "", Box::leak(posted.into_boxed_str())),
+        ("200 OK", "", include_str!("fixtures/message_status.json")),
+        ("403 Forbidden", "", r#"{"error":"Forbidden"}"#),
+    ]);
+    let api = Gitter::new("token")
+        .unwrap()
+        .with_api_base_url(base_url)
+        .with_retry_policy(RetryPolicy::none());
+    let room_id = RoomId::new("5330777dc3599d1de448e194").unwrap();
+    let other_room_id = RoomId::new("52b42a52ed5ab0b3bf051b93").unwrap();
+
+    let path = std::env::temp_dir().join(format!("gitter-outbox-{}.json", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+
+    {
+        let outbox = Outbox::open(path.clone())
+            .unwrap()
+            .with_retry_policy(RetryPolicy::new().base_delay(Duration::from_millis(1)).jitter(false));
+        assert!(outbox.enqueue(&room_id, "a-1", "first").unwrap());
+        assert!(outbox.enqueue(&room_id, "a-2", "second").unwrap());
+        assert!(outbox.enqueue(&other_room_id, "b-1", "other").unwrap());
+        assert!(!outbox.enqueue(&room_id, "a-1", "first again").unwrap());
+
+        // The failed message holds back its room, but not the other one
+        let report = outbox.deliver(&api).unwrap();
+        assert_eq!(DeliveryReport { sent: 1, failed: 0, pending: 2 }, report);
+        assert_eq!(Some(OutboxStatus::Pending), outbox.status("a-1"));
+        assert_eq!(1, outbox.get("a-1").unwrap().attempts);
+        assert_eq!(Some(OutboxStatus::Sent), outbox.status("b-1"));
+    }
+
+    assert!(requests.recv().unwrap().to_lowercase().contains("idempotency-key: a-1"));
+    assert!(requests.recv().unwrap().contains(r#""text":"other""#));
+    thread::sleep(Duration::from_millis(10));
+
+    // Reopened after a restart
+    let outbox = Outbox::open(path.clone()).unwrap();
+    assert_eq!(2, outbox.pending());
+
+    // The failed message is looked up in the room before it is sent again
+    let report = outbox.deliver(&api).unwrap();
+    assert_eq!(DeliveryReport { sent: 2, failed: 0, pending: 0 }, report);
+    assert!(requests.recv().unwrap().starts_with("GET /user "));
+    assert!(requests.recv().unwrap().starts_with("GET /rooms/5330777dc3599d1de448e194/chatMessages?limit=50 "));
+    assert!(requests.recv().unwrap().contains(r#""text":"second""#));
+    assert_eq!("5f1e9c0e3c1a4e2b6c8d0f5b", outbox.get("a-1").unwrap().message_id.unwrap().as_str());
+    assert!(outbox.items().iter().all(|item| item.message_id.is_some()));
+
+    // Permission errors are not retried
+    outbox.enqueue(&room_id, "a-3", "denied").unwrap();
+    assert_eq!(DeliveryReport { sent: 0, failed: 1, pending: 0 }, outbox.deliver(&api).unwrap());
+    assert_eq!(Some(OutboxStatus::Failed), outbox.status("a-3"));
+
+    outbox.remove_finished().unwrap();
+    assert!(Outbox::open(path.clone()).unwrap().items().is_empty());
+    std::fs::remove_file(&path).unwrap();
+}
+
+struct ReadOnlyMiddleware;
+
+impl Middleware for ReadOnlyMiddleware {
+    fn handle(&self, request: HttpRequest, next: Next) -> Result<HttpResponse, ApiError> {
+        match request.method {
+            HttpMethod::Get => next.run(request),
+            _ => Ok(HttpResponse::new(403, "read only")),
+        }
+    }
+}
+
+#[test]
+fn middleware_chain() {
+    let (base_url, requests) = serve_with_headers(vec![("200 OK", "", include_str!("fixtures/user_rooms.json"))]);
+    let timings = Arc::new(Mutex::new(Vec::new()));
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let log_lines = log.clone();
+
+    let api = Gitter::new("token")
+        .unwrap()
+        .with_api_base_url(base_url)
+        .with_middleware(LoggingMiddleware::with_writer(move |line| log_lines.lock().unwrap().push(line.to_owned())))
+        .with_middleware(TimingMiddleware::collect(timings.clone()))
+        .with_middleware(HeaderMiddleware::new().header("X-Request-Source", "cron"))
+        .with_middleware(ReadOnlyMiddleware);
+
+    assert_eq!(3, api.get_rooms().unwrap().len());
+    assert!(requests.recv().unwrap().to_lowercase().contains("x-request-source: cron"));
+
+    // Short-circuited by the read only middleware, the server is not called
+    let room_id = RoomId::new("5330777dc3599d1de448e194").unwrap();
+    match api.send_message(&room_id, "hello") {
+        Err(ApiError::PermissionDenied(ref message)) if message == "read only" => {}
+        other => panic!("expected permission denied, got {:?}", other),
+    }
+
+    let timings = timings.lock().unwrap();
+    assert_eq!(2, timings.len());
+    assert_eq!(HttpMethod::Get, timings[0].method);
+    assert_eq!(Some(200), timings[0].status);
+    assert_eq!(Some(403), timings[1].status);
+
+    let log = log.lock().unwrap();
+    assert!(log[0].starts_with("GET http://") && log[0].contains("/rooms -> 200"));
+    assert!(log[1].starts_with("POST ") && log[1].contains(" -> 403"));
+}
+
+#[test]
+fn instrument_paths_and_headers() {
+    assert_eq!(
+        "/rooms/{id}/chatMessages/{id}/readBy",
+        path_template("/rooms/5330777dc3599d1de448e194/chatMessages/53316dc47bfc1a000000000f/readBy?limit=5")
+    );
+    assert_eq!("/rooms", path_template("/rooms?q=gitter"));
+
+    let room_id = path_room_id("/rooms/5330777dc3599d1de448e194/users/53307734c3599d1de448e192");
+    assert_eq!(Some("5330777dc3599d1de448e194"), room_id.as_ref().map(|id| id.as_str()));
+    assert_eq!(None, path_room_id("/user/53307734c3599d1de448e192/rooms"));
+
+    let headers = vec![
+        ("authorization".to_owned(), "Bearer secret-token".to_owned()),
+        ("Accept".to_owned(), "application/json".to_owned()),
+    ];
+    let redacted = redact_headers(&headers);
+    assert_eq!("[redacted]", redacted[0].1);
+    assert_eq!("application/json", redacted[1].1);
+
+    let request = HttpRequest {
+        method: HttpMethod::Get,
+        url: "https://api.gitter.im/v1/rooms".to_owned(),
+        headers: headers.clone(),
+        body: None,
+    };
+    let debug = format!("{:?}", request);
+    assert!(!debug.contains("secret-token") && debug.contains("application/json"));
+
+    let mut response = HttpResponse::new(200, "[]");
+    response.headers.push(("Set-Cookie".to_owned(), "session=secret-cookie".to_owned()));
+    assert!(!format!("{:?}", response).contains("secret-cookie"));
+}
+
+#[test]
+fn secret_token_is_redacted() {
+    let token = SecretToken::new("secret-token");
+
+    assert_eq!("[redacted]", token.to_string());
+    assert!(!format!("{:?}", token).contains("secret-token"));
+    assert_eq!("secret-token", token.expose());
+
+    let api = Gitter::new("secret-token").unwrap();
+    assert!(!format!("{:?}", api).contains("secret-token"));
+}
+
+struct MapStore(Vec<(&'static str, &'static str, &'static str)>);
+
+impl SecretStore for MapStore {
+    fn get_secret(&self, service: &str, account: &str) -> Result<Option<SecretToken>, CredentialError> {
+        Ok(self.0.iter().find(|s| s.0 == service && s.1 == account).map(|s| SecretToken::new(s.2)))
+    }
+}
+
+/// Returns a copy of the provider's current token
+fn current_token<P: CredentialProvider>(provider: &P) -> Result<String, CredentialError> {
+    let mut current = String::new();
+    provider.with_token(&mut |token| current = token.to_string())?;
+    Ok(current)
+}
+
+#[test]
+fn credential_providers() {
+    std::env::set_var("GITTER_TEST_TOKEN", " env-token\n");
+    assert_eq!("env-token", current_token(&EnvToken::new("GITTER_TEST_TOKEN")).unwrap());
+    assert!(current_token(&EnvToken::new("GITTER_TEST_TOKEN_MISSING")).is_err());
+
+    let path = std::env::temp_dir().join(format!("gitter-token-{}", std::process::id()));
+    std::fs::write(&path, "file-token\n").unwrap();
+    let provider = FileToken::new(path.clone());
+    assert_eq!("file-token", current_token(&provider).unwrap());
+    std::fs::write(&path, "rotated-token\n").unwrap();
+    assert_eq!("rotated-token", current_token(&provider).unwrap());
+    std::fs::remove_file(&path).unwrap();
+    assert!(current_token(&provider).is_err());
+
+    let store = StoreToken::new(MapStore(vec![("gitter", "bot", "store-token")]), "gitter", "bot");
+    assert_eq!("store-token", current_token(&store).unwrap());
+    assert!(current_token(&StoreToken::new(MapStore(vec![]), "gitter", "bot")).is_err());
+}
+
+fn assert_shareable<T: Send + Sync + Clone + 'static>(_: &T) {}
+
+#[test]
+fn share_client_between_threads() {
+    let base_url = serve(vec![
+        ("200 OK", include_str!("fixtures/user_rooms.json")),
+        ("200 OK", include_str!("fixtures/user_rooms.json")),
+    ]);
+    let api = Gitter::new(String::from("token"))
+        .unwrap()
+        .with_api_base_url(base_url)
+        .with_cache(Duration::from_secs(60));
+    assert_shareable(&api);
+
+    let workers = (0..2)
+        .map(|_| {
+            let api = api.clone();
+            thread::spawn(move || api.get_rooms().unwrap().len())
+        })
+        .collect::<Vec<_>>();
+    for worker in workers {
+        assert_eq!(3, worker.join().unwrap());
+    }
+
+    // Clones share the cache, so the first response is served to all of them
+    assert_eq!(3, api.get_rooms().unwrap().len());
+    let stats = api.cache_stats().unwrap();
+    assert_eq!(3, stats.hits + stats.misses);
+    assert!(stats.hits >= 1);
+}
+
+#[test]
+fn reuse_http_client() {
+    let (base_url, requests) = serve_with_headers(vec![("200 OK", "", include_str!("fixtures/user_rooms.json"))]);
+    let client = reqwest::Client::builder().timeout(Duration::from_secs(5)).build().unwrap();
+    let api = Gitter::from_client("token", client).with_api_base_url(base_url);
+
+    assert_eq!(3, api.get_rooms().unwrap().len());
+    assert!(requests.recv().unwrap().to_lowercase().contains("authorization: bearer token"));
+}
+
+#[test]
+fn tls_config() {
+    let bundle = "-----BEGIN CERTIFICATE-----\nnot a certificate\n-----END CERTIFICATE-----\n\
+                  -----BEGIN CERTIFICATE-----\nnot one either\n-----END CERTIFICATE-----\n";
+    let tls = TlsConfig::new().ca_bundle_pem(bundle);
+    assert!(format!("{:?}", tls).contains("root_certificates: 2"));
+
+    assert!(Gitter::builder("token").tls(tls).build().is_err());
+    assert!(Gitter::builder("token").tls(TlsConfig::new()).timeout(Duration::from_secs(5)).build().is_ok());
+}
+
+#[test]
+fn rotate_token_without_rebuilding_client() {
+    let (base_url, requests) = serve_with_headers(vec![
+        ("200 OK", "", include_str!("fixtures/user_rooms.json")),
+        ("200 OK", "", include_str!("fixtures/user_rooms.json")),
+    ]);
+    let token = Arc::new(RotatingToken::new("old-token"));
+    let api = Gitter::from_credentials(token.clone()).unwrap().with_api_base_url(base_url);
+
+    api.get_rooms().unwrap();
+    assert!(requests.recv().unwrap().to_lowercase().contains("authorization: bearer old-token"));
+
+    token.rotate("new-token");
+    api.get_rooms().unwrap();
+    assert!(requests.recv().unwrap().to_lowercase().contains("authorization: bearer new-token"));
+
+    match Gitter::from_credentials(EnvToken::new("GITTER_TEST_TOKEN_MISSING")).unwrap().get_rooms() {
+        Err(ApiError::Credentials(_)) => {}
+        other => panic!("expected credentials error, got {:?}", other),
+    }
+}
+
+/// Opens `url` like a browser and returns the response
+fn browse(url: &str) -> String {
+    let url = url.trim_start_matches("http://");
+    let path_start = url.find('/').unwrap();
+    let mut stream = TcpStream::connect(&url[..path_start]).unwrap();
+
+    write!(stream, "GET {} HTTP/1.1\r\nHost: {}\r\n\r\n", &url[path_start..], &url[..path_start]).unwrap();
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response
+}
+
+#[test]
+fn oauth_flow() {
+    let (token_url, token_requests) =
+        serve_with_headers(vec![("200 OK", "", r#"{"access_token":"oauth-token","token_type":"Bearer"}"#)]);
+    let (api_url, api_requests) = serve_with_headers(vec![("200 OK", "", include_str!("fixtures/user_rooms.json"))]);
+
+    let authorization = OAuth::new("client-id", "client-secret")
+        .authorize_url("https://gitter.test/login/oauth/authorize")
+        .token_url(format!("{}/login/oauth/token", token_url))
+        .api_base_url(api_url)
+        .timeout(Duration::from_secs(10))
+        .client(reqwest::Client::new())
+        .start()
+        .unwrap();
+
+    let url = authorization.url().to_owned();
+    assert!(url.starts_with("https://gitter.test/login/oauth/authorize?client_id=client-id&redirect_uri=http"));
+    assert!(url.contains("&response_type=code&state="));
+
+    let state = url.split("state=").nth(1).unwrap().to_owned();
+    assert_eq!(32, state.len());
+    let redirect_uri = authorization.redirect_uri().to_owned();
+    assert!(redirect_uri.starts_with("http://127.0.0.1:"));
+    let browser = thread::spawn(move || {
+        let favicon = browse(&redirect_uri.replace("/callback", "/favicon.ico"));
+        let redirect = browse(&format!("{}?code=the-code&state={}", redirect_uri, state));
+        (favicon, redirect)
+    });
+
+    let api = authorization.wait().unwrap();
+    let (favicon, redirect) = browser.join().unwrap();
+    assert!(favicon.starts_with("HTTP/1.1 404"));
+    assert!(redirect.starts_with("HTTP/1.1 200"));
+
+    let token_request = token_requests.recv().unwrap();
+    assert!(token_request.contains("code=the-code"));
+    assert!(token_request.contains("client_secret=client-secret"));
+    assert!(token_request.contains("grant_type=authorization_code"));
+
+    assert_eq!(3, api.get_rooms().unwrap().len());
+    assert!(api_requests.recv().unwrap().to_lowercase().contains("authorization: bearer oauth-token"));
+}
+
+#[test]
+fn oauth_waits_past_bad_connections() {
+    let (token_url, token_requests) =
+        serve_with_headers(vec![("200 OK", "", r#"{"access_token":"oauth-token","token_type":"Bearer"}"#)]);
+
+    let authorization = OAuth::new("client-id", "client-secret")
+        .token_url(format!("{}/login/oauth/token", token_url))
+        .timeout(Duration::from_secs(10))
+        .client(reqwest::Client::new())
+        .start()
+        .unwrap();
+
+    let state = authorization.url().split("state=").nth(1).unwrap().to_owned();
+    let redirect_uri = authorization.redirect_uri().to_owned();
+    let browser = thread::spawn(move || {
+        // Connects and sends nothing, like a preconnect of the browser
+        let idle = TcpStream::connect(redirect_uri.trim_start_matches("http://").split('/').next().unwrap()).unwrap();
+
+        let forged = browse(&format!("{}?code=forged&state=forged", redirect_uri));
+        let stateless = browse(&format!("{}?code=forged", redirect_uri));
+        let redirect = browse(&format!("{}?code=the-code&state={}", redirect_uri, state));
+        drop(idle);
+        (forged, stateless, redirect)
+    });
+
+    let token = authorization.wait_for_token().unwrap();
+    assert_eq!("oauth-token", token.expose());
+
+    let (forged, stateless, redirect) = browser.join().unwrap();
+    assert!(forged.starts_with("HTTP/1.1 400"));
+    assert!(stateless.starts_with("HTTP/1.1 400"));
+    assert!(redirect.starts_with("HTTP/1.1 200"));
+    assert!(token_requests.recv().unwrap().contains("code=the-code"));
+
+    let authorization = OAuth::new("client-id", "client-secret")
+        .timeout(Duration::from_millis(100))
+        .start()
+        .unwrap();
+
+    match authorization.wait_for_token() {
+        Err(OAuthError::Timeout) => {}
+        other => panic!("expected timeout, got {:?}", other),
+    }
+}
+
+#[test]
+fn deserialize_message_thread_parent() {
+    let json = include_str!("fixtures/message_thread_parent.json");
+    let message = serde_json::from_str::<Message>(json).unwrap();
+
+    assert!(!message.is_thread_reply());
+    assert_eq!(Some(2), message.thread_message_count);
+    assert_eq!(Some(vec![]), message.meta);
+
+    let mention = &message.mentions[0];
+    assert_eq!("all", mention.screen_name);
+    assert_eq!(None, mention.user_id);
+    assert_eq!(Some(true), mention.group);
+
+    assert_eq!(Some("shmutalov/gitter-rs".to_owned()), message.issues[0].repo);
+    assert_eq!(None, message.issues[0].is_pull_request);
+    assert_eq!(Some(true), message.issues[1].is_pull_request);
+    assert_eq!(Some("issue".to_owned()), message.urls[0].url_type);
+
+    assert_round_trip::<Message>(json);
+}
+
+#[test]
+fn deserialize_message_meta() {
+    let json = include_str!("fixtures/message_status.json")
+        .replace(r#""meta": []"#, r#""meta": [{"type": "embed", "height": 120}, "legacy"]"#);
+    let message = serde_json::from_str::<Message>(&json).unwrap();
+
+    match message.meta.as_ref().unwrap()[..] {
+        [MessageMeta::Entry(ref entry), MessageMeta::Other(ref other)] => {
+            assert_eq!("embed", entry.meta_type);
+            assert_eq!(Some(&serde_json::Value::from(120)), entry.extra.get("height"));
+            assert_eq!("legacy", other);
+        }
+        ref other => panic!("unexpected meta {:?}", other),
+    }
+    assert_round_trip::<Message>(&json);
+
+    match from_str_strict::<Message>(&json) {
+        Err(SchemaError::Drift(drift)) => assert_eq!(vec!["meta[0].height", "meta[1]"], drift.unknown_fields),
+        other => panic!("expected schema drift, got {:?}", other),
+    }
+}
+
+#[test]
+fn deserialize_message_thread_reply() {
+    let json = include_str!("fixtures/message_thread_reply.json");
+    let message = serde_json::from_str::<Message>(json).unwrap();
+
+    assert!(message.is_thread_reply());
+    assert_eq!("5e3d3b2ad73408ce4fd8a9a1", message.parent_id.as_ref().unwrap().as_str());
+    assert_eq!(None, message.thread_message_count);
+    assert!(message.edited_at.is_some());
+    assert_eq!(None, message.issues[0].repo);
+
+    assert_round_trip::<Message>(json);
+}
+
+#[test]
+fn deserialize_message_virtual_user() {
+    let json = include_str!("fixtures/message_virtual_user.json");
+    let message = serde_json::from_str::<Message>(json).unwrap();
+
+    let virtual_user = message.virtual_user.as_ref().unwrap();
+    assert_eq!("matrix", virtual_user.user_type);
+    assert_eq!("alice:matrix.org", virtual_user.external_id);
+    assert_eq!("Alice", message.sender_name());
+
+    assert_round_trip::<Message>(json);
+}
+
+#[test]
+fn deserialize_message_status() {
+    let json = include_str!("fixtures/message_status.json");
+    let message = serde_json::from_str::<Message>(json).unwrap();
+
+    assert_eq!(Some(true), message.status);
+    assert_eq!(None, message.virtual_user);
+    assert_eq!("Sherzod Mutalov", message.sender_name());
+
+    assert_round_trip::<Message>(json);
+}
+
+#[test]
+fn deserialize_lenient_room() {
+    let json = r#"{
+        "id": "5330777dc3599d1de448e194",
+        "name": "gitterHQ",
+        "url": "/gitterHQ",
+        "githubType": "ORG",
+        "premium": true
+    }"#;
+    let room = serde_json::from_str::<Room>(json).unwrap();
+
+    assert_eq!("", room.topic);
+    assert!(!room.room_member);
+    assert_eq!(Some(&serde_json::Value::Bool(true)), room.extra.get("premium"));
+    assert!(serde_json::to_string(&room).unwrap().contains("\"premium\":true"));
+
+    match from_str_strict::<Room>(json) {
+        Err(SchemaError::Drift(drift)) => {
+            assert_eq!(vec!["premium".to_owned()], drift.unknown_fields);
+            assert!(drift.missing_fields.contains(&"topic".to_owned()));
+            assert!(drift.missing_fields.contains(&"roomMember".to_owned()));
+        }
+        other => panic!("expected schema drift, got {:?}", other),
+    }
+}
+
+#[test]
+fn deserialize_strict_nested_unknown_fields() {
+    let json = include_str!("fixtures/message_thread_reply.json")
+        .replace("\"number\": \"44\"", "\"number\": \"44\", \"state\": \"closed\"");
+
+    match from_str_strict::<Vec<Message>>(&format!("[{}]", json)) {
+        Err(SchemaError::Drift(drift)) => {
+            assert_eq!(vec!["[0].issues[0].state".to_owned()], drift.unknown_fields);
+            assert!(drift.missing_fields.is_empty());
+        }
+        other => panic!("expected schema drift, got {:?}", other),
+    }
+}
+
+#[test]
+fn deserialize_strict_fixtures() {
+    assert!(from_str_strict::<Message>(include_str!("fixtures/message_thread_parent.json")).is_ok());
+    assert!(from_str_strict::<Message>(include_str!("fixtures/message_thread_reply.json")).is_ok());
+    assert!(from_str_strict::<Message>(include_str!("fixtures/message_virtual_user.json")).is_ok());
+    assert!(from_str_strict::<Message>(include_str!("fixtures/message_status.json")).is_ok());
 }
 
 #[test]
 fn serialize_out_message() {
     let out_msg = OutMessage {
-        text: "test `message` from @shmutalov"
+        text: "test `message` from @shmutalov",
+        parent_id: None,
     };
     let out_msg_expected = "{\"text\":\"test `message` from @shmutalov\"}";
     let out_msg_json = serde_json::to_string(&out_msg).unwrap();
@@ -37,6 +1372,307 @@ fn serialize_out_message() {
     assert_eq!(&out_msg_expected, &out_msg_json);
 }
 
+#[test]
+fn serialize_update_room() {
+    let update = UpdateRoom::new()
+        .topic("Gitter API in Rust")
+        .tags(vec!["rust", "api"])
+        .public(true);
+    let update_json = serde_json::to_string(&update).unwrap();
+
+    assert_eq!("{\"topic\":\"Gitter API in Rust\",\"tags\":\"rust,api\",\"security\":\"PUBLIC\"}", &update_json);
+
+    let update = UpdateRoom::from_noindex(true);
+    let update_json = serde_json::to_string(&update).unwrap();
+
+    assert_eq!("{\"noindex\":true}", &update_json);
+}
+
+#[test]
+fn build_message() {
+    let user = serde_json::from_str::<User>(r#"{
+        "id": "53307734c3599d1de448e192",
+        "username": "malditogeek",
+        "displayName": "Mauro Pompilio",
+        "url": "/malditogeek"
+    }"#).unwrap();
+
+    let text = MessageBuilder::new()
+        .mention(&user)
+        .text(" build of *master* failed in `")
+        .code("a`b")
+        .text("`, see ")
+        .issue(42)
+        .text(" and ")
+        .repo_issue("shmutalov/gitter-rs", 7)
+        .code_block("rust", "let s = \"```\";\n")
+        .quote("- not a list\n@not_a_mention")
+        .list(vec!["1. first", "[second]"])
+        .link("logs (full)", "https://ci.example.com/build (1)")
+        .build()
+        .unwrap();
+
+    assert_eq!(
+        "@malditogeek build of \\*master\\* failed in \\`\
+         ``a`b``\\`, see #42 and shmutalov/gitter-rs#7\n\
+         ````rust\nlet s = \"```\";\n````\n\
+         > \\- not a list\n\
+         > \\@not\\_a\\_mention\n\n\
+         - 1\\. first\n\
+         - \\[second\\]\n\n\
+         [logs (full)](https://ci.example.com/build%20%281%29)",
+        text
+    );
+}
+
+#[test]
+fn build_message_escapes_block_syntax() {
+    // Rules, heading underlines and ordered lists with either marker
+    let text = MessageBuilder::new().text("Title\n===\n- - -\n---\n1) one\n2.").build().unwrap();
+    assert_eq!("Title\n\\===\n\\- - -\n\\---\n1\\) one\n2\\.", text);
+
+    // The language cannot end the fence line or turn the fence into inline code
+    let text = MessageBuilder::new().code_block("rust\n```\n@/all", "x").build().unwrap();
+    assert_eq!("```rust\nx\n```", text);
+    let text = MessageBuilder::new().code_block("`rust", "x").build().unwrap();
+    assert_eq!("```\nx\n```", text);
+
+    // Only username characters belong to the mention
+    let user = serde_json::from_str::<User>(r#"{
+        "id": "53307734c3599d1de448e192",
+        "username": "bob*](https://example.com)",
+        "displayName": "Bob",
+        "url": "/bob"
+    }"#).unwrap();
+    let text = MessageBuilder::new().mention(&user).build().unwrap();
+    assert_eq!("@bob\\*\\](https://example.com)", text);
+}
+
+#[test]
+fn build_message_too_long() {
+    let text = "a".repeat(MAX_MESSAGE_LENGTH);
+
+    assert!(MessageBuilder::new().text(&text).build().is_ok());
+
+    let err = MessageBuilder::new().text(&text).text("!").build().unwrap_err();
+    assert_eq!(MAX_MESSAGE_LENGTH + 1, err.length);
+    assert_eq!(MAX_MESSAGE_LENGTH, err.max_length);
+
+    assert!(MessageBuilder::new().max_length(10).text("12345678901").build().is_err());
+}
+
+#[test]
+fn split_long_message() {
+    assert_eq!(vec!["short".to_string()], split_message("short", 100));
+    assert!(split_message("", 100).is_empty());
+    assert!(split_message(" \n\n", 100).is_empty());
+
+    let text = "First paragraph,\nstill the first.\n\n\
+                Second paragraph.\n\n\
+                ```rust\nfn main() {\n    println!(\"hello\");\n}\n\nfn other() {}\n```\n\
+                Last words of the message";
+    let chunks = split_message(text, 40);
+
+    for chunk in &chunks {
+        assert!(chunk.encode_utf16().count() <= 40, "{:?}", chunk);
+    }
+    assert_eq!("First paragraph,\nstill the first.", chunks[0]);
+    assert_eq!("Second paragraph.", chunks[1]);
+
+    // The code block is closed and reopened with its language
+    let code = chunks.iter().filter(|chunk| chunk.contains("fn ")).collect::<Vec<_>>();
+    assert!(code.len() > 1);
+    for chunk in code {
+        assert!(chunk.starts_with("```rust\n"), "{:?}", chunk);
+        assert!(chunk.ends_with("\n```"), "{:?}", chunk);
+    }
+
+    // Long lines are split between words
+    let words = "word ".repeat(20);
+    for chunk in split_message(&words, 12) {
+        assert!(chunk.chars().count() <= 12);
+        assert!(chunk.split_whitespace().all(|word| word == "word"));
+    }
+
+    // A code block whose first line does not fit starts in the next message
+    let text = format!("Intro\n```text\n{}\n```", "word ".repeat(10));
+    let chunks = split_message(&text, 20);
+    assert_eq!("Intro", chunks[0]);
+    for chunk in &chunks[1..] {
+        assert!(chunk.starts_with("```text\nword"), "{:?}", chunk);
+        assert!(chunk.ends_with("\n```") && chunk.chars().count() <= 20, "{:?}", chunk);
+    }
+}
+
+#[test]
+fn send_long_message_in_thread() {
+    let (base_url, requests) = serve_with_headers(vec![
+        ("200 OK", "", include_str!("fixtures/message_status.json")),
+        ("200 OK", "", include_str!("fixtures/message_status.json")),
+        ("200 OK", "", include_str!("fixtures/message_status.json")),
+    ]);
+    let limits = SendLimits::new().per_room(RateLimit::new(1, Duration::from_millis(100)));
+    let api = Gitter::new("token").unwrap().with_api_base_url(base_url).with_send_limits(limits);
+    let room_id = RoomId::new("5330777dc3599d1de448e194").unwrap();
+    let text = format!("{}\n\n{}", "a".repeat(MAX_MESSAGE_LENGTH - 10), "b".repeat(20));
+    let started_at = std::time::Instant::now();
+
+    // A message sent meanwhile waits until the whole sequence is sent
+    let other = {
+        let (api, room_id) = (api.clone(), room_id.clone());
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            api.send_message(&room_id, "other").unwrap()
+        })
+    };
+    let messages = api.send_long_message(&room_id, &text, true).unwrap();
+    other.join().unwrap();
+
+    // Every message counts against the limits
+    assert!(started_at.elapsed() >= Duration::from_millis(200));
+    assert_eq!(2, messages.len());
+    let first = requests.recv().unwrap();
+    assert!(first.contains("aaaa") && !first.contains("parentId"));
+    let second = requests.recv().unwrap();
+    assert!(second.contains(&format!(r#"{{"text":"{}","parentId":"5f1e9c0e3c1a4e2b6c8d0f5a"}}"#, "b".repeat(20))));
+    assert!(requests.recv().unwrap().contains(r#"{"text":"other"}"#));
+
+    assert!(api.send_long_message(&room_id, " \n", false).unwrap().is_empty());
+}
+
+/// Parses message text locally and compares the result with the entities computed by Gitter
+fn assert_entities_match(message: &Message, users: &[User]) {
+    let mut entities = parse_entities(&message.text);
+    entities.resolve_mentions(users);
+
+    assert_eq!(message.mentions, entities.mentions);
+    assert_eq!(message.issues, entities.issues);
+    assert_eq!(message.urls, entities.urls);
+}
+
+#[test]
+fn parse_message_entities() {
+    let parent = serde_json::from_str::<Message>(include_str!("fixtures/message_thread_parent.json")).unwrap();
+    let reply = serde_json::from_str::<Message>(include_str!("fixtures/message_thread_reply.json")).unwrap();
+    let status = serde_json::from_str::<Message>(include_str!("fixtures/message_status.json")).unwrap();
+    let virtual_user = serde_json::from_str::<Message>(include_str!("fixtures/message_virtual_user.json")).unwrap();
+    let users = vec![parent.from.clone(), reply.from.clone()];
+
+    assert_entities_match(&parent, &users);
+    assert_entities_match(&reply, &users);
+    assert_entities_match(&status, &users);
+    assert_entities_match(&virtual_user, &users);
+}
+
+#[test]
+fn parse_message_entities_skips_code_and_escapes() {
+    let entities = parse_entities(
+        "mail me at user@example.com, \\@escaped, `@in_code #1`\n\
+         ```\n@in_block #2 https://example.com/block\n```\n\
+         see [docs](https://example.com/docs_(v2)). &#39; org/repo#3 #4x foo#5 @bob",
+    );
+
+    let mentions = entities.mentions.iter().map(|m| m.screen_name.as_str()).collect::<Vec<_>>();
+    assert_eq!(vec!["bob"], mentions);
+
+    assert_eq!(1, entities.issues.len());
+    assert_eq!("3", entities.issues[0].number);
+    assert_eq!(Some("org/repo".to_owned()), entities.issues[0].repo);
+
+    assert_eq!(1, entities.urls.len());
+    assert_eq!("https://example.com/docs_(v2)", entities.urls[0].url);
+}
+
+#[test]
+fn render_message_html() {
+    let html = "<span data-link-type=\"mention\" data-screen-name=\"bob\" class=\"mention\">@bob</span> \
+                see <a href=\"https://example.com/docs\">the docs</a> &amp; <code>cargo test</code> \
+                <img class=\"emoji\" alt=\":tada:\" title=\":tada:\" src=\"tada.png\"><br>\
+                <blockquote>quoted text</blockquote>\
+                <pre><code class=\"rust\">fn main() {\n    println!(\"hi\");\n}\n</code></pre>\
+                <ul><li>one</li><li>two</li></ul>";
+
+    let plain = Renderer::plain().render_html(html);
+    assert_eq!(
+        "@bob see the docs (https://example.com/docs) & cargo test 🎉\n\
+         \n\
+         > quoted text\n\
+         \n    fn main() {\n        println!(\"hi\");\n    }\n\
+         \n\
+         • one\n\
+         • two",
+        plain
+    );
+
+    let ansi = Renderer::ansi().render_html(html);
+    assert!(ansi.starts_with("\x1b[1;33m@bob\x1b[0m see \x1b[4;34mthe\x1b[0m"));
+    assert!(ansi.contains("\x1b[36mcargo\x1b[0m"));
+    assert!(ansi.contains("    \x1b[36mfn main() {\x1b[0m"));
+}
+
+#[test]
+fn render_message_wraps_lines() {
+    let renderer = Renderer::plain().width(20);
+
+    let html = "<blockquote>a quote which is too long for one line</blockquote>\
+                <pre><code>let code_is_never_wrapped = true;</code></pre>";
+    assert_eq!(
+        "> a quote which is\n\
+         > too long for one\n\
+         > line\n\
+         \n    let code_is_never_wrapped = true;",
+        renderer.render_html(html)
+    );
+
+    assert_eq!(
+        "plain text 😄 is\nwrapped\nnext line",
+        renderer.render_text("plain text :smile: is wrapped\nnext line")
+    );
+}
+
+#[test]
+fn render_non_ascii_and_control_chars() {
+    let renderer = Renderer::plain();
+
+    assert_eq!("é", renderer.render_html("<b>é</b>"));
+    assert_eq!("日本語 ✔", renderer.render_html("日本語 &#x2714;"));
+
+    // Terminal escapes in the content are dropped
+    assert_eq!("hi ]0;pwned there", renderer.render_html("hi &#27;]0;pwned&#7; there"));
+    assert_eq!("red [31mtext", renderer.render_text("red \u{1b}[31mtext\u{9b}"));
+    assert!(!Renderer::ansi().render_html("<b>&#27;]0;x&#7;</b>").contains('\u{7}'));
+    assert_eq!(
+        "\u{1b}[1m]0;x\u{1b}[0m",
+        Renderer::ansi().render_html("<b>&#27;]0;x&#7;</b>")
+    );
+}
+
+#[test]
+fn render_message_text() {
+    let text = "@bob see `cargo test` and [the docs](https://example.com/docs), \\@not_a_mention\n\
+                > quoted :tada:\n\
+                \n\
+                ```rust\n\
+                fn main() {}\n\
+                ```\n\
+                bye https://example.com";
+
+    assert_eq!(
+        "@bob see cargo test and the docs (https://example.com/docs), @not_a_mention\n\
+         \n\
+         > quoted 🎉\n\
+         \n    fn main() {}\n\
+         \n\
+         bye https://example.com",
+        Renderer::plain().render_text(text)
+    );
+
+    let ansi = Renderer::ansi().render_text(text);
+    assert!(ansi.starts_with("\x1b[1;33m@bob\x1b[0m see \x1b[36mcargo\x1b[0m"));
+    assert!(ansi.contains("\x1b[4;34mthe\x1b[0m"));
+    assert!(ansi.contains("    \x1b[36mfn main() {}\x1b[0m"));
+}
+
 #[test]
 fn api_get_user() {
     let api = get_gitter_api();
@@ -130,13 +1766,26 @@ fn api_get_message() {
     assert!(message.is_ok());
 }
 
+#[test]
+fn api_get_message_read_by() {
+    let api = get_gitter_api();
+
+    let rooms = api.get_rooms().unwrap();
+    let messages = api.get_messages(&rooms[0].id, None).unwrap();
+
+    let read_by = api.get_message_read_by(&rooms[0].id, &messages[0].id);
+
+    assert!(read_by.is_ok());
+}
+
 #[test]
 fn api_get_room_id() {
     let api = get_gitter_api();
 
     let rooms = api.get_rooms().unwrap();
     let room = rooms.into_iter().find(|x| x.uri.is_some()).unwrap();
-    let room_id = api.get_room_id(room.uri.unwrap_or_else(|| "".to_owned()));
+    let uri = room.uri.unwrap().parse::<RoomUri>().unwrap();
+    let room_id = api.get_room_id(&uri);
 
     assert!(room_id.is_ok());
 }
@@ -153,6 +1802,16 @@ fn api_search_rooms() {
     assert!(!search_result.unwrap().rooms.is_empty());
 }
 
+#[test]
+fn api_get_room_permissions() {
+    let api = get_gitter_api();
+
+    let rooms = api.get_rooms().unwrap();
+    let permissions = api.get_room_permissions(&rooms[0].id);
+
+    assert!(permissions.is_ok());
+}
+
 #[test]
 fn api_get_groups() {
     let api = get_gitter_api();
@@ -212,7 +1871,7 @@ fn api_get_user_channels() {
 #[test]
 fn api_send_message() {
     let api = get_gitter_api();
-    let room_id = api.get_room_id("gitter-rs/testing").unwrap();
+    let room_id = api.get_room_id(&"gitter-rs/testing".parse().unwrap()).unwrap();
 
     let msg = "@shmutalov this is a `test` message.\n\n```rust\nfn main() {}```";
     let result = api.send_message(&room_id, &msg).unwrap();